@@ -0,0 +1,277 @@
+//! Streaming algorithms over the present elements of a [`VirtualArray`].
+//!
+//! Every function visits the array page by page through its buffer, so no more than
+//! `buffer_size` pages are held in memory, plus whatever the function itself accumulates.
+
+use std::{cmp::Ordering, collections::BinaryHeap, ops::Add};
+
+use crate::{metadata, page, Result, Storage, VirtualArray};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Calls `f` with the index and the value of every present element in index order.
+    pub fn try_for_each_present<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &Item) -> Result<()>,
+    {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let len = self.len();
+
+        for page_index in 0..self.count_used_pages() {
+            let page_start = page_index * elements_count_on_page;
+            let page = self.get_page(page_index)?;
+
+            for index_on_page in page.bitmap.iter_ones() {
+                if page_start + index_on_page >= len {
+                    break;
+                }
+
+                f(page_start + index_on_page, page.get(index_on_page).unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn fold<Item, Store, PSerializer, MSerializer, B, F>(
+    array: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    init: B,
+    mut f: F,
+) -> Result<B>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+    F: FnMut(B, &Item) -> B,
+{
+    let mut accumulator = Some(init);
+    array.try_for_each_present(|_, item| {
+        accumulator = accumulator.take().map(|accumulator| f(accumulator, item));
+        Ok(())
+    })?;
+
+    Ok(accumulator.unwrap())
+}
+
+/// Folds the present elements using the first one as the initial value. Returns `None` if
+/// there are no present elements.
+pub fn reduce<Item, Store, PSerializer, MSerializer, F>(
+    array: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    mut f: F,
+) -> Result<Option<Item>>
+where
+    Item: Default + Clone,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+    F: FnMut(Item, &Item) -> Item,
+{
+    fold(array, None, |accumulator, item| match accumulator {
+        None => Some(item.clone()),
+        Some(accumulator) => Some(f(accumulator, item)),
+    })
+}
+
+/// Writes inclusive prefix sums of `source` into `target`. An element of `target` is present
+/// only where the element of `source` is present, absent elements are skipped by the sum.
+pub fn prefix_sum<Item, Store, PSerializer, MSerializer, TStore, TPSerializer, TMSerializer>(
+    source: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    target: &mut VirtualArray<'_, Item, TStore, TPSerializer, TMSerializer>,
+) -> Result<()>
+where
+    Item: Default + Clone + Add<Output = Item>,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+    TStore: Storage,
+    TPSerializer: page::Serializer<Item>,
+    TMSerializer: metadata::Serializer,
+{
+    target.check_writable()?;
+    target.clear(..source.len())?;
+
+    let mut sum: Option<Item> = None;
+    source.try_for_each_present(|index, item| {
+        let next_sum = match sum.take() {
+            None => item.clone(),
+            Some(sum) => sum + item.clone(),
+        };
+
+        target.put(index, Some(next_sum.clone()))?;
+        sum = Some(next_sum);
+        Ok(())
+    })?;
+
+    target.save()
+}
+
+/// Counts present elements per bucket, where bucket `i` holds keys in
+/// `edges[i]..edges[i + 1]`. Keys outside of all buckets are not counted.
+pub fn histogram<Item, Store, PSerializer, MSerializer, K, F>(
+    array: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    edges: &[K],
+    mut key: F,
+) -> Result<Vec<usize>>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+    K: PartialOrd,
+    F: FnMut(&Item) -> K,
+{
+    let mut counts = vec![0; edges.len().saturating_sub(1)];
+
+    array.try_for_each_present(|_, item| {
+        let key = key(item);
+        let bucket = edges.partition_point(|edge| *edge <= key);
+
+        if bucket > 0 && bucket < edges.len() {
+            counts[bucket - 1] += 1;
+        }
+        Ok(())
+    })?;
+
+    Ok(counts)
+}
+
+/// Returns up to `k` present elements with the largest keys as `(index, value)` pairs, ordered
+/// from the largest key. Among equal keys, elements with smaller indices win.
+pub fn top_k<Item, Store, PSerializer, MSerializer, K, F>(
+    array: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    k: usize,
+    mut key: F,
+) -> Result<Vec<(usize, Item)>>
+where
+    Item: Default + Clone,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+    K: Ord,
+    F: FnMut(&Item) -> K,
+{
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+
+    array.try_for_each_present(|index, item| {
+        if k == 0 {
+            return Ok(());
+        }
+
+        let candidate = Candidate {
+            key: key(item),
+            index,
+            item: None,
+        };
+        let is_better = heap.len() < k
+            || heap
+                .peek()
+                .is_some_and(|worst: &Candidate<K, Item>| candidate < *worst);
+
+        if is_better {
+            heap.push(Candidate {
+                item: Some(item.clone()),
+                ..candidate
+            });
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|candidate| (candidate.index, candidate.item.unwrap()))
+        .collect())
+}
+
+/// Chooses `count` present elements uniformly at random as `(index, value)` pairs in one
+/// pass over the array. The same `seed` gives the same sample for the same array.
+pub fn sample<Item, Store, PSerializer, MSerializer>(
+    array: &mut VirtualArray<'_, Item, Store, PSerializer, MSerializer>,
+    count: usize,
+    seed: u64,
+) -> Result<Vec<(usize, Item)>>
+where
+    Item: Default + Clone,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    let mut random = SplitMix64(seed);
+    let mut reservoir = Vec::with_capacity(count);
+    let mut seen = 0u64;
+
+    array.try_for_each_present(|index, item| {
+        seen += 1;
+
+        if reservoir.len() < count {
+            reservoir.push((index, item.clone()));
+        } else {
+            let position = random.next_below(seen) as usize;
+            if position < count {
+                reservoir[position] = (index, item.clone());
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(reservoir)
+}
+
+/// Ordered so that the "smallest" candidate is the best one, which makes the max-heap keep
+/// the worst of the kept candidates on top.
+struct Candidate<K, Item> {
+    key: K,
+    index: usize,
+    item: Option<Item>,
+}
+
+impl<K: Ord, Item> Ord for Candidate<K, Item> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .cmp(&self.key)
+            .then_with(|| self.index.cmp(&other.index))
+    }
+}
+
+impl<K: Ord, Item> PartialOrd for Candidate<K, Item> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, Item> PartialEq for Candidate<K, Item> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, Item> Eq for Candidate<K, Item> {}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_below(&mut self, bound: u64) -> u64 {
+        ((self.next() as u128 * bound as u128) >> 64) as u64
+    }
+}
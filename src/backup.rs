@@ -0,0 +1,342 @@
+use std::io::{self, ErrorKind, Read, SeekFrom, Write};
+
+use crate::{
+    checksum::Checksum,
+    metadata::{self, Metadata},
+    page, PositionalStorage, Result, Snapshot, Storage, VirtualArray, VirtualArrayError,
+};
+
+const INCREMENTAL_MAGIC: &[u8; 8] = b"VAINCR01";
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Copies the array to `dest` as an image that can be opened as an array or passed to
+    /// [`restore_from`](Self::restore_from). The copy is taken from a snapshot; to keep
+    /// writing while it is made, take the snapshot and call [`Snapshot::backup_to`] on another
+    /// thread instead.
+    pub fn backup_to<D, P>(&mut self, dest: D, progress: P) -> Result<D>
+    where
+        D: Storage,
+        P: FnMut(usize, usize),
+    {
+        self.snapshot()?.backup_to(dest, progress)
+    }
+
+    /// Copies the pages changed after `generation` to `dest`, see
+    /// [`Snapshot::incremental_backup_since`].
+    pub fn incremental_backup_since<D, P>(
+        &mut self,
+        generation: u64,
+        dest: D,
+        progress: P,
+    ) -> Result<D>
+    where
+        D: Storage,
+        P: FnMut(usize, usize),
+    {
+        self.snapshot()?
+            .incremental_backup_since(generation, dest, progress)
+    }
+}
+
+impl<Item, Store, PSerializer> Snapshot<Item, Store, PSerializer>
+where
+    Item: Default,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+{
+    /// Writes the header and all pages of the snapshot to `dest` in place layout, calling
+    /// `progress` with the number of copied and all pages after each page.
+    pub fn backup_to<D, P>(&self, mut dest: D, mut progress: P) -> Result<D>
+    where
+        D: Storage,
+        P: FnMut(usize, usize),
+    {
+        let pages_count = self.count_pages();
+
+        dest.seek_to_start()?;
+        dest.write_all(self.header())?;
+
+        for page_index in 0..pages_count {
+            dest.write_all(&self.read_page_bytes(page_index)?)?;
+            progress(page_index + 1, pages_count);
+        }
+
+        dest.sync()?;
+        Ok(dest)
+    }
+
+    /// Writes the pages changed after `generation`, usually the [`generation`](Self::generation)
+    /// of the snapshot the previous backup was made from, to `dest`. The result can be applied
+    /// with [`VirtualArray::apply_incremental`] to an array restored from that backup, and
+    /// `progress` is called with the number of copied and all changed pages after each page.
+    ///
+    /// The image starts with a magic, the base generation and the header of the snapshot,
+    /// followed by the number of pages and each page with its index. A checksum over everything
+    /// before it closes the image. Fails with [`io::ErrorKind::Unsupported`] if the array
+    /// has an unversioned header, which cannot be read back from the image.
+    pub fn incremental_backup_since<D, P>(
+        &self,
+        generation: u64,
+        dest: D,
+        mut progress: P,
+    ) -> Result<D>
+    where
+        D: Storage,
+        P: FnMut(usize, usize),
+    {
+        if !self.has_versioned_header() {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "incremental backups need the versioned header format",
+            )
+            .into());
+        }
+
+        let mut changed_pages = Vec::new();
+        for page_index in 0..self.count_pages() {
+            if self.read_page_generation(page_index)? > generation {
+                changed_pages.push(page_index);
+            }
+        }
+
+        let mut dest = Checksummed::new(dest);
+        dest.inner.seek_to_start()?;
+        dest.write_all(INCREMENTAL_MAGIC)?;
+        dest.write_all(&generation.to_le_bytes())?;
+        dest.write_all(self.header())?;
+        dest.write_all(&(changed_pages.len() as u64).to_le_bytes())?;
+
+        for (i, &page_index) in changed_pages.iter().enumerate() {
+            dest.write_all(&(page_index as u64).to_le_bytes())?;
+            dest.write_all(&self.read_page_bytes(page_index)?)?;
+            progress(i + 1, changed_pages.len());
+        }
+
+        let checksum = dest.checksum.finish();
+        let mut dest = dest.inner;
+        dest.write_all(&checksum.to_le_bytes())?;
+        dest.sync()?;
+        Ok(dest)
+    }
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Replaces the contents of the array with a backup image. The whole image is read and
+    /// checked against the page size of the array before anything is replaced.
+    ///
+    /// The restore is not staged: the array is resized and its pages are overwritten one after
+    /// another. If writing fails, the array is left with a mix of its previous and restored
+    /// pages, and the restore has to be retried before the array is used again.
+    pub fn restore_from<S: Storage>(&mut self, mut src: S) -> Result<()> {
+        self.check_writable()?;
+        src.seek_to_start()?;
+        let image = MSerializer::deserialize::<S, Item>(&mut src, self.metadata.signature)?;
+
+        if image.data_chunk_size != self.metadata.data_chunk_size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "backup image has a different page size",
+            )
+            .into());
+        }
+        self.check_item_layout(&image)?;
+
+        if self.versions.is_some() && image.generation < self.metadata.generation {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "backup image is older than the version history, use rollback_to instead",
+            )
+            .into());
+        }
+
+        for page_index in 0..image.count_pages::<Item>() {
+            read_image_page::<Item, S, PSerializer, MSerializer>(&mut src, &image, page_index)?;
+        }
+
+        self.save()?;
+        self.pages.clear();
+        self.resize(image.array_size)?;
+        self.metadata.generation = image.generation;
+
+        for page_index in 0..image.count_pages::<Item>() {
+            let mut page =
+                read_image_page::<Item, S, PSerializer, MSerializer>(&mut src, &image, page_index)?;
+            self.write_page(&mut page)?;
+        }
+
+        self.metadata.length = image.length;
+        self.write_metadata()
+    }
+
+    /// Applies an incremental backup made since the generation of the array, which is the
+    /// generation of the backup it was restored from or of the last applied increment. The
+    /// whole increment is read and checked before anything is replaced.
+    pub fn apply_incremental<S: Storage>(&mut self, src: S) -> Result<()> {
+        self.check_writable()?;
+        let mut src = Checksummed::new(src);
+        src.inner.seek_to_start()?;
+        let image = self.read_incremental(&mut src, false)?;
+
+        src.inner.seek_to_start()?;
+        src.checksum = Checksum::new(0);
+        self.read_incremental(&mut src, true)?;
+
+        self.metadata.length = image.length;
+        self.write_metadata()
+    }
+
+    /// Fails like opening the array does if the image holds items of another layout. Layouts
+    /// are only compared if both headers record one.
+    fn check_item_layout(&self, image: &Metadata) -> Result<()> {
+        if let (Some(found), Some(expected)) = (image.item_layout, self.metadata.item_layout) {
+            if !found.matches(&expected) {
+                return Err(VirtualArrayError::ItemTypeMismatch { expected, found });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads an incremental backup. The pages are only checked unless `should_apply` is set,
+    /// in which case the array takes the size and the generation of the backup and its pages
+    /// are written.
+    fn read_incremental<S: Storage>(
+        &mut self,
+        src: &mut Checksummed<S>,
+        should_apply: bool,
+    ) -> Result<Metadata<'metadata>> {
+        let invalid_data =
+            |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_owned());
+
+        let mut magic = [0; INCREMENTAL_MAGIC.len()];
+        src.read_exact(&mut magic)?;
+        if &magic != INCREMENTAL_MAGIC {
+            return Err(invalid_data("not an incremental backup").into());
+        }
+
+        let base_generation = read_u64(src)?;
+        let image = MSerializer::deserialize::<_, Item>(src, self.metadata.signature)?;
+        if image.data_chunk_size != self.metadata.data_chunk_size {
+            return Err(invalid_data("backup image has a different page size").into());
+        }
+        self.check_item_layout(&image)?;
+        if base_generation != self.metadata.generation {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "incremental backup does not start at the generation of the array",
+            )
+            .into());
+        }
+
+        if should_apply {
+            self.save()?;
+            self.pages.clear();
+            self.resize(image.array_size)?;
+            self.metadata.generation = image.generation;
+        }
+
+        let elements_count_on_page = image.count_elements_on_page::<Item>();
+        let mut page_bytes = vec![0; image.page_size::<Item, PSerializer>()];
+        for _ in 0..read_u64(src)? {
+            let page_index = read_u64(src)? as usize;
+            if page_index >= image.count_pages::<Item>() {
+                return Err(invalid_data("incremental backup page is out of bounds").into());
+            }
+
+            src.read_exact(&mut page_bytes)?;
+            let mut page = page::deserialize_stored::<_, PSerializer, _>(
+                &mut page_bytes.as_slice(),
+                page_index,
+                elements_count_on_page,
+                image.has_page_generations(),
+            )?;
+            if should_apply {
+                self.write_page(&mut page)?;
+            }
+        }
+
+        let checksum = src.checksum.finish();
+        if read_u64(&mut src.inner)? != checksum {
+            return Err(invalid_data("incremental backup checksum mismatch").into());
+        }
+
+        Ok(image)
+    }
+}
+
+/// Reads or writes through `inner`, keeping a checksum of the bytes.
+struct Checksummed<T> {
+    inner: T,
+    checksum: Checksum,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            checksum: Checksum::new(0),
+        }
+    }
+}
+
+impl<T: Read> Read for Checksummed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.checksum.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Checksummed<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_image_page<Item, S, PSerializer, MSerializer>(
+    src: &mut S,
+    image: &Metadata,
+    page_index: usize,
+) -> Result<page::Page<Item>>
+where
+    Item: Default,
+    S: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    let offset = S::get_page_offset::<Item, PSerializer, MSerializer>(page_index, image);
+    src.seek(SeekFrom::Start(offset))?;
+
+    Ok(page::deserialize_stored::<_, PSerializer, _>(
+        src,
+        page_index,
+        image.count_elements_on_page::<Item>(),
+        image.has_page_generations(),
+    )?)
+}
@@ -0,0 +1,139 @@
+use std::cmp::Ordering;
+
+use crate::{metadata, page, Result, Storage, VirtualArray};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Returns the index of the first present element for which `pred` returns `false`, or the
+    /// array length if there is no such element. The present elements must be partitioned by
+    /// `pred`. Absent slots are skipped and never returned, except that they may make up the
+    /// tail past the last present element.
+    ///
+    /// Pages are searched first using their last present element, then the found page is
+    /// searched on its own, so only about `log2(pages count)` pages are loaded. Pages without
+    /// present elements are skipped by reading their bitmaps only.
+    pub fn partition_point<P>(&mut self, mut pred: P) -> Result<usize>
+    where
+        P: FnMut(&Item) -> bool,
+    {
+        let mut low = 0;
+        let mut high = self.count_used_pages();
+        let mut found_page = None;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            let Some((page_index, last_index_on_page)) = self.find_non_empty_page(middle..high)?
+            else {
+                high = middle;
+                continue;
+            };
+
+            let page = self.get_page(page_index)?;
+            if pred(page.get(last_index_on_page).unwrap()) {
+                low = page_index + 1;
+            } else {
+                found_page = Some(page_index);
+                high = middle;
+            }
+        }
+
+        let Some(page_index) = found_page else {
+            return Ok(self.len());
+        };
+
+        let page_start = page_index * self.metadata.count_elements_on_page::<Item>();
+        let len = self.len();
+        let page = self.get_page(page_index)?;
+        let present_indices = page
+            .bitmap
+            .iter_ones()
+            .take_while(|index_on_page| page_start + index_on_page < len)
+            .collect::<Vec<_>>();
+        let position = present_indices
+            .partition_point(|&index_on_page| pred(page.get(index_on_page).unwrap()));
+
+        Ok(page_start + present_indices[position])
+    }
+
+    /// Binary searches the present elements with a comparator function. Returns `Ok` with the
+    /// index of a matching element, or `Err` with the index [`partition_point`] would return
+    /// for elements that are less than the searched one.
+    ///
+    /// [`partition_point`]: Self::partition_point
+    pub fn binary_search_by<F>(&mut self, mut f: F) -> Result<std::result::Result<usize, usize>>
+    where
+        F: FnMut(&Item) -> Ordering,
+    {
+        let index = self.partition_point(|item| f(item) == Ordering::Less)?;
+
+        if index < self.len() && self.get(index)?.map(&mut f) == Some(Ordering::Equal) {
+            Ok(Ok(index))
+        } else {
+            Ok(Err(index))
+        }
+    }
+
+    pub fn binary_search(&mut self, value: &Item) -> Result<std::result::Result<usize, usize>>
+    where
+        Item: Ord,
+    {
+        self.binary_search_by(|item| item.cmp(value))
+    }
+
+    pub fn binary_search_by_key<K, F>(
+        &mut self,
+        key: &K,
+        mut f: F,
+    ) -> Result<std::result::Result<usize, usize>>
+    where
+        K: Ord,
+        F: FnMut(&Item) -> K,
+    {
+        self.binary_search_by(|item| f(item).cmp(key))
+    }
+
+    /// Returns the index of the first present element that is not less than `value`.
+    pub fn lower_bound(&mut self, value: &Item) -> Result<usize>
+    where
+        Item: Ord,
+    {
+        self.partition_point(|item| item < value)
+    }
+
+    /// Returns the index of the first present element that is greater than `value`.
+    pub fn upper_bound(&mut self, value: &Item) -> Result<usize>
+    where
+        Item: Ord,
+    {
+        self.partition_point(|item| item <= value)
+    }
+
+    /// Finds the first page of `pages` with a present element and returns its index with the
+    /// index of its last present element.
+    fn find_non_empty_page(
+        &mut self,
+        pages: std::ops::Range<usize>,
+    ) -> Result<Option<(usize, usize)>> {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+
+        for page_index in pages {
+            let used_on_page =
+                (self.len() - page_index * elements_count_on_page).min(elements_count_on_page);
+            let last_present =
+                self.with_bitmap(page_index, |bitmap| bitmap.prev_set_from(used_on_page - 1))?;
+
+            if let Some(last_index_on_page) = last_present {
+                return Ok(Some((page_index, last_index_on_page)));
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -1,13 +1,20 @@
 use std::{
-    fs::{File, OpenOptions},
+    fs::{File, OpenOptions, TryLockError},
     marker::PhantomData,
+    num::NonZeroU64,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::Storage;
+use crate::{
+    checksum::Checksum,
+    metadata::{CommitMode, ItemLayout},
+    Storage,
+};
 
 use super::{
     metadata::{self, Metadata},
-    page, Result, VirtualArray, DEFAULT_SIGNATURE,
+    page, Result, VirtualArray, VirtualArrayError, DEFAULT_SIGNATURE,
 };
 
 pub struct VirtualArrayBuilder<'signature, Source, Item, PSerializer, MSerializer, BufferSize> {
@@ -16,11 +23,41 @@ pub struct VirtualArrayBuilder<'signature, Source, Item, PSerializer, MSerialize
     page_serializer: PSerializer,
     metadata_serializer: MSerializer,
     buffer_size: BufferSize,
+    wal: Option<LogSource>,
+    versions: Option<(LogSource, u64)>,
+    commit_mode: CommitMode,
+    read_only: bool,
+    lock_behavior: LockBehavior,
+    item_schema: Option<NonZeroU64>,
     _item_marker: PhantomData<Item>,
 }
 
 pub struct NoneType;
 
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What opening an array file that is locked by another process does. Writers hold an
+/// exclusive lock on the file and read-only arrays a shared one, for as long as the array or
+/// a snapshot of it is alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockBehavior {
+    /// Fails with [`VirtualArrayError::Locked`] right away.
+    #[default]
+    FailFast,
+    /// Retries until the lock is released, and fails once the timeout has passed.
+    Wait(Duration),
+    /// Does not lock the file. Read-only arrays that follow a writer in another process with
+    /// [`VirtualArray::refresh`] need it, as the writer's exclusive lock keeps them from being
+    /// opened otherwise.
+    Unlocked,
+}
+
+enum LogSource {
+    Storage(Box<dyn Storage + Send>),
+    /// A file next to the array file.
+    Sidecar,
+}
+
 impl<'signature> VirtualArrayBuilder<'signature, NoneType, NoneType, NoneType, NoneType, NoneType> {
     pub fn from_storage(
         storage: impl Storage,
@@ -38,6 +75,12 @@ impl<'signature> VirtualArrayBuilder<'signature, NoneType, NoneType, NoneType, N
             metadata_serializer: metadata::DefaultSerializer,
             signature: DEFAULT_SIGNATURE,
             buffer_size: NoneType,
+            wal: None,
+            versions: None,
+            commit_mode: CommitMode::InPlace,
+            read_only: false,
+            lock_behavior: LockBehavior::FailFast,
+            item_schema: None,
             _item_marker: PhantomData,
         }
     }
@@ -58,6 +101,12 @@ impl<'signature> VirtualArrayBuilder<'signature, NoneType, NoneType, NoneType, N
             metadata_serializer: metadata::DefaultSerializer,
             signature: DEFAULT_SIGNATURE,
             buffer_size: NoneType,
+            wal: None,
+            versions: None,
+            commit_mode: CommitMode::InPlace,
+            read_only: false,
+            lock_behavior: LockBehavior::FailFast,
+            item_schema: None,
             _item_marker: PhantomData,
         }
     }
@@ -85,6 +134,12 @@ where
             page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size: self.buffer_size,
+            wal: self.wal,
+            versions: self.versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
     }
@@ -117,6 +172,12 @@ impl<'signature, Source, Item, PSerializer, BufferSize>
             page_serializer: self.page_serializer,
             metadata_serializer,
             buffer_size: self.buffer_size,
+            wal: self.wal,
+            versions: self.versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
     }
@@ -135,9 +196,64 @@ impl<'signature, Source, PSerializer, MSerializer, Item, BufferSize>
             page_serializer: self.page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size: self.buffer_size,
+            wal: self.wal,
+            versions: self.versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
     }
+
+    /// Logs every batch of modified pages to `storage` before it is written to the array. The
+    /// log is replayed when the array is opened. For an array file, `storage` is used instead
+    /// of the file that [`with_write_ahead_log`](Self::with_write_ahead_log) keeps next to it.
+    pub fn write_ahead_log(mut self, storage: impl Storage + Send + 'static) -> Self {
+        self.wal = Some(LogSource::Storage(Box::new(storage)));
+        self
+    }
+
+    /// Keeps previous page images in `storage` for `retention` generations, so elements can be
+    /// read as of an earlier generation and the array can be rolled back to it.
+    pub fn version_log(mut self, storage: impl Storage + Send + 'static, retention: u64) -> Self {
+        self.versions = Some((LogSource::Storage(Box::new(storage)), retention));
+        self
+    }
+
+    /// Sets how modified pages are written to a created array. Opened arrays keep the mode
+    /// they were created with. Shadow paging cannot be combined with a write-ahead log.
+    ///
+    /// In shadow paging mode every commit rewrites the whole page table, 8 bytes per page of
+    /// the array, and syncs the storage twice, so commits of large arrays are costly even when
+    /// they change a single page.
+    pub fn commit_mode(mut self, commit_mode: CommitMode) -> Self {
+        self.commit_mode = commit_mode;
+        self
+    }
+
+    /// Opens the array without modifying it. Modifications fail with
+    /// [`VirtualArrayError::ReadOnly`], and an array file is opened for reading only and
+    /// locked shared. Arrays cannot be created read-only.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Records a hash of the item type name or schema in a created array, and checks it when
+    /// an array that has one is opened. The size and alignment of items are always checked.
+    pub fn item_schema(mut self, schema: NonZeroU64) -> Self {
+        self.item_schema = Some(schema);
+        self
+    }
+
+    /// Like [`item_schema`](Self::item_schema) with a hash of `type_name`, where a hash of 0 is
+    /// recorded as 1.
+    pub fn item_type_name(self, type_name: &str) -> Self {
+        let mut checksum = Checksum::new(0);
+        checksum.update(type_name.as_bytes());
+        self.item_schema(NonZeroU64::new(checksum.finish()).unwrap_or(NonZeroU64::MIN))
+    }
 }
 
 impl<'signature, Source, Item, PSerializer, MSerializer>
@@ -153,6 +269,12 @@ impl<'signature, Source, Item, PSerializer, MSerializer>
             page_serializer: self.page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size,
+            wal: self.wal,
+            versions: self.versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
     }
@@ -170,6 +292,12 @@ impl<'signature, Source, Item, PSerializer, MSerializer, BufferSize>
             page_serializer: self.page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size: self.buffer_size,
+            wal: self.wal,
+            versions: self.versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
     }
@@ -188,27 +316,36 @@ where
         array_size: usize,
         data_chunk_size: usize,
     ) -> Result<VirtualArray<'signature, Item, Source, PSerializer, MSerializer>> {
-        let metadata = Metadata::new::<Item>(self.signature, data_chunk_size, array_size)?;
+        if self.read_only {
+            return Err(VirtualArrayError::ReadOnly);
+        }
+
+        let mut metadata = Metadata::new::<Item>(self.signature, data_chunk_size, array_size)?;
+        metadata.commit_mode = self.commit_mode;
+        metadata.item_layout = Some(ItemLayout::of::<Item>(self.item_schema));
         MSerializer::serialize(&mut self.source, &metadata)?;
         self.source.flush()?;
 
-        let elements_count_on_page = metadata.count_elements_on_page::<Item>();
-
-        for i in 0..(array_size / elements_count_on_page + 1) {
-            self.source
-                .seek_to_page::<Item, PSerializer, MSerializer>(i, &metadata)?;
-            PSerializer::serialize_zeroed(&mut self.source, elements_count_on_page)?;
-            self.source.flush()?;
+        let pages_count = metadata.count_pages::<Item>();
+        let mut virtual_array = VirtualArray::new(
+            self.source,
+            metadata,
+            self.page_serializer,
+            self.metadata_serializer,
+            self.buffer_size,
+        );
+        if self.commit_mode == CommitMode::ShadowPaging {
+            virtual_array.attach_shadow(true)?;
         }
+        virtual_array.write_zeroed_pages(0..pages_count)?;
+        virtual_array.storage.flush()?;
 
-        let virtual_array = VirtualArray {
-            pages: Vec::with_capacity(self.buffer_size),
-            metadata,
-            buffer_size: self.buffer_size,
-            storage: self.source,
-            page_serializer: self.page_serializer,
-            metadata_serializer: self.metadata_serializer,
-        };
+        if let Some(LogSource::Storage(wal)) = self.wal {
+            virtual_array.attach_wal(wal, false)?;
+        }
+        if let Some((LogSource::Storage(versions), retention)) = self.versions {
+            virtual_array.attach_versions(versions, retention, true)?;
+        }
 
         Ok(virtual_array)
     }
@@ -217,37 +354,92 @@ where
         mut self,
     ) -> Result<VirtualArray<'signature, Item, Source, PSerializer, MSerializer>> {
         let metadata = MSerializer::deserialize::<Source, Item>(&mut self.source, self.signature)?;
+        let expected = ItemLayout::of::<Item>(self.item_schema);
+        if let Some(found) = metadata.item_layout {
+            if !found.matches(&expected) {
+                return Err(VirtualArrayError::ItemTypeMismatch { expected, found });
+            }
+        }
 
-        let virtual_array = VirtualArray {
-            pages: Vec::with_capacity(self.buffer_size),
+        let mut virtual_array = VirtualArray::new(
+            self.source,
             metadata,
-            buffer_size: self.buffer_size,
-            storage: self.source,
-            page_serializer: self.page_serializer,
-            metadata_serializer: self.metadata_serializer,
-        };
+            self.page_serializer,
+            self.metadata_serializer,
+            self.buffer_size,
+        );
+        virtual_array.is_read_only = self.read_only;
+        if virtual_array.metadata.commit_mode == CommitMode::ShadowPaging {
+            virtual_array.attach_shadow(false)?;
+        }
+        if let Some(LogSource::Storage(wal)) = self.wal {
+            virtual_array.attach_wal(wal, true)?;
+        }
+        if let Some((LogSource::Storage(versions), retention)) = self.versions {
+            virtual_array.attach_versions(versions, retention, false)?;
+        }
 
         Ok(virtual_array)
     }
 }
 
-impl<'signature, 'file_name, Item, PSerializer, MSerializer>
-    VirtualArrayBuilder<'signature, &'file_name str, Item, PSerializer, MSerializer, usize>
+impl<'signature, Item, PSerializer, MSerializer>
+    VirtualArrayBuilder<'signature, &str, Item, PSerializer, MSerializer, usize>
 where
     Item: Default,
     PSerializer: page::Serializer<Item>,
     MSerializer: metadata::Serializer,
 {
+    /// Sets what happens when the array file is locked by another process.
+    pub fn lock_behavior(mut self, lock_behavior: LockBehavior) -> Self {
+        self.lock_behavior = lock_behavior;
+        self
+    }
+
+    /// Keeps a write-ahead log in a `.wal` file next to the array file.
+    pub fn with_write_ahead_log(mut self) -> Self {
+        self.wal = Some(LogSource::Sidecar);
+        self
+    }
+
+    /// Keeps a version log in a `.versions` file next to the array file, see
+    /// [`version_log`](VirtualArrayBuilder::version_log).
+    pub fn with_version_log(mut self, retention: u64) -> Self {
+        self.versions = Some((LogSource::Sidecar, retention));
+        self
+    }
+
     pub fn create(
         self,
         array_size: usize,
         data_chunk_size: usize,
     ) -> Result<VirtualArray<'signature, Item, File, PSerializer, MSerializer>> {
+        if self.read_only {
+            return Err(VirtualArrayError::ReadOnly);
+        }
+
+        // The file is only truncated once it is locked, so an array in use stays intact.
         let file = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .write(true)
             .read(true)
             .open(self.source)?;
+        lock_file(&file, true, self.lock_behavior)?;
+        file.set_len(0)?;
+        let wal = self
+            .wal
+            .map(|wal| open_sidecar(self.source, wal, "wal"))
+            .transpose()?;
+        let versions = self
+            .versions
+            .map(|(versions, retention)| {
+                Ok::<_, VirtualArrayError>((
+                    open_sidecar(self.source, versions, "versions")?,
+                    retention,
+                ))
+            })
+            .transpose()?;
 
         VirtualArrayBuilder {
             source: file,
@@ -255,6 +447,12 @@ where
             page_serializer: self.page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size: self.buffer_size,
+            wal,
+            versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
         .create(array_size, data_chunk_size)
@@ -263,9 +461,23 @@ where
     pub fn open(self) -> Result<VirtualArray<'signature, Item, File, PSerializer, MSerializer>> {
         let file = OpenOptions::new()
             .create(false)
-            .write(true)
+            .write(!self.read_only)
             .read(true)
             .open(self.source)?;
+        lock_file(&file, !self.read_only, self.lock_behavior)?;
+        let wal = self
+            .wal
+            .map(|wal| open_sidecar(self.source, wal, "wal"))
+            .transpose()?;
+        let versions = self
+            .versions
+            .map(|(versions, retention)| {
+                Ok::<_, VirtualArrayError>((
+                    open_sidecar(self.source, versions, "versions")?,
+                    retention,
+                ))
+            })
+            .transpose()?;
 
         VirtualArrayBuilder {
             source: file,
@@ -273,8 +485,59 @@ where
             page_serializer: self.page_serializer,
             metadata_serializer: self.metadata_serializer,
             buffer_size: self.buffer_size,
+            wal,
+            versions,
+            commit_mode: self.commit_mode,
+            read_only: self.read_only,
+            lock_behavior: self.lock_behavior,
+            item_schema: self.item_schema,
             _item_marker: PhantomData,
         }
         .open()
     }
 }
+
+/// Opens a log file named after the array file with `extension`, keeping existing contents.
+fn open_sidecar(file_name: &str, source: LogSource, extension: &str) -> Result<LogSource> {
+    match source {
+        LogSource::Sidecar => {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .read(true)
+                .open(format!("{}.{}", file_name, extension))?;
+
+            Ok(LogSource::Storage(Box::new(file)))
+        }
+        source => Ok(source),
+    }
+}
+
+/// Takes an advisory lock on the file, exclusive for writers and shared for readers.
+fn lock_file(file: &File, is_exclusive: bool, lock_behavior: LockBehavior) -> Result<()> {
+    let deadline = match lock_behavior {
+        LockBehavior::FailFast => None,
+        LockBehavior::Wait(timeout) => Some(Instant::now() + timeout),
+        LockBehavior::Unlocked => return Ok(()),
+    };
+
+    loop {
+        let result = if is_exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock)
+                if deadline.is_some_and(|deadline| Instant::now() < deadline) =>
+            {
+                thread::sleep(LOCK_RETRY_INTERVAL);
+            }
+            Err(TryLockError::WouldBlock) => return Err(VirtualArrayError::Locked),
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/// 64-bit FNV-1a hash, used to detect torn or stale records.
+#[derive(Debug, Clone)]
+pub(crate) struct Checksum {
+    state: u64,
+}
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Checksum {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut checksum = Self {
+            state: OFFSET_BASIS,
+        };
+        checksum.update(&seed.to_le_bytes());
+        checksum
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.state
+    }
+}
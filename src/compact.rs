@@ -0,0 +1,47 @@
+use crate::{metadata, page, Result, Storage, VirtualArray};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Moves all present elements to a dense prefix of the array keeping their order and
+    /// returns the number of present elements, see [`compact_with`](Self::compact_with).
+    pub fn compact(&mut self, truncate: bool) -> Result<usize> {
+        self.compact_with(truncate, |_, _| {})
+    }
+
+    /// Moves all present elements to a dense prefix of the array keeping their order, calls
+    /// `on_move` with the old and the new index of every present element and returns their
+    /// number. If `truncate` is set, the array is resized to the number of present elements.
+    /// Only the page buffer is used, regardless of the array size.
+    pub fn compact_with<F>(&mut self, truncate: bool, mut on_move: F) -> Result<usize>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.check_writable()?;
+        let mut new_index = 0;
+        let mut from = 0;
+
+        while let Some(old_index) = self.next_present(from)? {
+            if old_index != new_index {
+                let value = self.take(old_index)?;
+                self.put(new_index, value)?;
+            }
+            on_move(old_index, new_index);
+
+            new_index += 1;
+            from = old_index + 1;
+        }
+        self.save()?;
+
+        if truncate {
+            self.resize(new_index)?;
+        }
+
+        Ok(new_index)
+    }
+}
@@ -0,0 +1,136 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::page::Page;
+
+/// Mutable access to a present element.
+///
+/// The owning page is marked as modified only when the element is actually written
+/// through, so reading via the guard never causes the page to be saved.
+#[derive(Debug)]
+pub struct ElementGuard<'page, Item> {
+    page: &'page mut Page<Item>,
+    index_on_page: usize,
+}
+
+impl<'page, Item> ElementGuard<'page, Item> {
+    pub(crate) fn new(page: &'page mut Page<Item>, index_on_page: usize) -> Option<Self> {
+        page.get(index_on_page)?;
+
+        Some(Self {
+            page,
+            index_on_page,
+        })
+    }
+}
+
+impl<Item> Deref for ElementGuard<'_, Item> {
+    type Target = Item;
+
+    fn deref(&self) -> &Item {
+        self.page.get(self.index_on_page).unwrap()
+    }
+}
+
+impl<Item> DerefMut for ElementGuard<'_, Item> {
+    fn deref_mut(&mut self) -> &mut Item {
+        self.page.mark_modified();
+        self.page.get_mut(self.index_on_page).unwrap()
+    }
+}
+
+/// A view into a single element of the array, which may either be present or absent.
+///
+/// Constructed by [`VirtualArray::entry`](crate::VirtualArray::entry). All changes are
+/// made to the buffered page and are written to the storage together with it.
+#[derive(Debug)]
+pub enum Entry<'page, Item> {
+    Occupied(OccupiedEntry<'page, Item>),
+    Vacant(VacantEntry<'page, Item>),
+}
+
+#[derive(Debug)]
+pub struct OccupiedEntry<'page, Item> {
+    page: &'page mut Page<Item>,
+    index_on_page: usize,
+}
+
+#[derive(Debug)]
+pub struct VacantEntry<'page, Item> {
+    page: &'page mut Page<Item>,
+    index_on_page: usize,
+}
+
+impl<'page, Item: Default> Entry<'page, Item> {
+    pub(crate) fn new(page: &'page mut Page<Item>, index_on_page: usize) -> Self {
+        if page.get(index_on_page).is_some() {
+            Self::Occupied(OccupiedEntry {
+                page,
+                index_on_page,
+            })
+        } else {
+            Self::Vacant(VacantEntry {
+                page,
+                index_on_page,
+            })
+        }
+    }
+
+    pub fn or_insert(self, default: Item) -> &'page mut Item {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> Item>(self, default: F) -> &'page mut Item {
+        match self {
+            Self::Occupied(entry) => entry.into_mut(),
+            Self::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'page mut Item {
+        self.or_insert_with(Item::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut Item)>(self, f: F) -> Self {
+        match self {
+            Self::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Self::Occupied(entry)
+            }
+            Self::Vacant(entry) => Self::Vacant(entry),
+        }
+    }
+}
+
+impl<'page, Item: Default> OccupiedEntry<'page, Item> {
+    pub fn get(&self) -> &Item {
+        self.page.get(self.index_on_page).unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut Item {
+        self.page.mark_modified();
+        self.page.get_mut(self.index_on_page).unwrap()
+    }
+
+    pub fn into_mut(self) -> &'page mut Item {
+        self.page.mark_modified();
+        self.page.get_mut(self.index_on_page).unwrap()
+    }
+
+    pub fn insert(&mut self, value: Item) -> Item {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> Item {
+        self.page.take(self.index_on_page).unwrap()
+    }
+}
+
+impl<'page, Item> VacantEntry<'page, Item> {
+    pub fn insert(self, value: Item) -> &'page mut Item {
+        self.page.set(self.index_on_page, value);
+        self.page.get_mut(self.index_on_page).unwrap()
+    }
+}
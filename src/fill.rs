@@ -0,0 +1,71 @@
+use std::ops::{Range, RangeBounds};
+
+use crate::{metadata, page, page::Page, Result, Storage, VirtualArray};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    pub fn fill<R: RangeBounds<usize>>(&mut self, range: R, value: Item) -> Result<()>
+    where
+        Item: Clone,
+    {
+        self.check_writable()?;
+        let range = self.get_range(range)?;
+        self.for_each_page_range(range, |page, range_on_page| {
+            page.fill(range_on_page, value.clone())
+        })
+    }
+
+    pub fn clear<R: RangeBounds<usize>>(&mut self, range: R) -> Result<()> {
+        self.check_writable()?;
+        let range = self.get_range(range)?;
+        self.for_each_page_range(range, |page, range_on_page| page.clear(range_on_page))
+    }
+
+    pub fn clear_all(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.pages.clear();
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        for page_index in 0..self.metadata.count_pages::<Item>() {
+            self.write_page(&mut Page::empty(page_index, elements_count_on_page))?;
+        }
+        self.storage.flush()?;
+
+        Ok(())
+    }
+
+    /// Applies `f` to every page touched by `range`. Pages that are fully covered by the range
+    /// are not read from the storage, `f` gets a fresh empty page instead.
+    fn for_each_page_range<F>(&mut self, range: Range<usize>, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Page<Item>, Range<usize>),
+    {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+
+        for (page_index, range_on_page) in self.get_page_ranges(range).collect::<Vec<_>>() {
+            let used_on_page = self.metadata.count_used_on_page::<Item>(page_index);
+            let is_fully_covered = range_on_page == (0..used_on_page);
+
+            if !is_fully_covered {
+                f(self.get_page(page_index)?, range_on_page);
+                continue;
+            }
+
+            let mut fresh_page = Page::empty(page_index, elements_count_on_page);
+            f(&mut fresh_page, range_on_page);
+
+            match self.find_buffered_page(page_index) {
+                Some(buffered_page) => *buffered_page = fresh_page,
+                None => self.write_page(&mut fresh_page)?,
+            }
+        }
+
+        self.save()
+    }
+}
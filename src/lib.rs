@@ -1,14 +1,44 @@
+pub mod algo;
+mod backup;
+mod binary_search;
 mod builder;
+mod checksum;
+mod compact;
+mod entry;
+mod fill;
 pub mod metadata;
+mod movement;
 pub mod page;
+mod parallel;
+mod refresh;
+mod search;
+mod shadow;
+mod snapshot;
+mod sort;
+mod transaction;
+mod vec;
+mod verify;
+mod versions;
+mod wal;
 
-pub use builder::VirtualArrayBuilder;
+pub use builder::{LockBehavior, VirtualArrayBuilder};
+pub use entry::{ElementGuard, Entry, OccupiedEntry, VacantEntry};
+pub use snapshot::Snapshot;
+pub use sort::SortOptions;
+pub use transaction::Transaction;
+pub use vec::VirtualVec;
+pub use verify::ShutdownStatus;
+pub use versions::VersionIter;
 
 use std::{
     error::Error,
     fmt::{Debug, Display},
     fs::File,
     io::{Read, Seek, Write},
+    mem::ManuallyDrop,
+    ops::{Bound, Range, RangeBounds},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 type BytesCount = usize;
@@ -19,18 +49,26 @@ pub trait Storage: Read + Write + Seek + Debug {
         Ok(())
     }
 
+    fn set_len(&mut self, _size: u64) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+
     fn get_page_offset<Item, PSerializer, MSerializer>(
         page_index: usize,
         metadata: &metadata::Metadata,
     ) -> u64
     where
+        Self: Sized,
         Item: Default,
         PSerializer: page::Serializer<Item>,
         MSerializer: metadata::Serializer,
     {
-        let count_of_elements_on_page = metadata.count_elements_on_page::<Item>();
         let metadata_size_in_bytes = MSerializer::get_metadata_size_in_bytes(metadata);
-        let page_size_in_bytes = PSerializer::get_page_size_in_bytes(count_of_elements_on_page);
+        let page_size_in_bytes = metadata.page_size::<Item, PSerializer>();
 
         (metadata_size_in_bytes + page_index * page_size_in_bytes) as u64
     }
@@ -41,6 +79,7 @@ pub trait Storage: Read + Write + Seek + Debug {
         metadata: &metadata::Metadata,
     ) -> std::io::Result<()>
     where
+        Self: Sized,
         Item: Default,
         PSerializer: page::Serializer<Item>,
         MSerializer: metadata::Serializer,
@@ -53,11 +92,93 @@ pub trait Storage: Read + Write + Seek + Debug {
     }
 }
 
-use crate::page::Page;
+use crate::{
+    page::Page,
+    shadow::ShadowTable,
+    snapshot::SnapshotRegistry,
+    versions::{Version, VersionLog},
+    wal::Wal,
+};
+
+impl Storage for File {
+    fn set_len(&mut self, size: u64) -> std::io::Result<()> {
+        File::set_len(self, size)
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// A storage that can be read and written at given offsets through a shared reference, which
+/// lets several threads access it at once.
+pub trait PositionalStorage: Storage + Sync {
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> std::io::Result<()>;
+
+    fn write_all_at(&self, buffer: &[u8], offset: u64) -> std::io::Result<()>;
 
-impl Storage for File {}
+    /// Opens another handle to the same storage. Handles may share the cursor, so the new
+    /// handle is only accessed at given offsets.
+    fn try_clone(&self) -> std::io::Result<Self>
+    where
+        Self: Sized;
+}
 
-const DEFAULT_SIGNATURE: &[u8] = &[b'V', b'M'];
+#[cfg(unix)]
+impl PositionalStorage for File {
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buffer, offset)
+    }
+
+    fn write_all_at(&self, buffer: &[u8], offset: u64) -> std::io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(self, buffer, offset)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        File::try_clone(self)
+    }
+}
+
+#[cfg(windows)]
+impl PositionalStorage for File {
+    fn read_exact_at(&self, mut buffer: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buffer.is_empty() {
+            match self.seek_read(buffer, offset)? {
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                count => {
+                    buffer = &mut buffer[count..];
+                    offset += count as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buffer: &[u8], mut offset: u64) -> std::io::Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buffer.is_empty() {
+            match self.seek_write(buffer, offset)? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                count => {
+                    buffer = &buffer[count..];
+                    offset += count as u64;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        File::try_clone(self)
+    }
+}
+
+const DEFAULT_SIGNATURE: &[u8] = b"VM";
 
 #[derive(Debug)]
 pub struct VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
@@ -68,11 +189,26 @@ where
     MSerializer: metadata::Serializer,
 {
     metadata: metadata::Metadata<'metadata>,
-    storage: Store,
+    storage: ManuallyDrop<Store>,
+    #[allow(dead_code)]
     page_serializer: PSerializer,
+    #[allow(dead_code)]
     metadata_serializer: MSerializer,
     pages: Vec<Page<Item>>,
     buffer_size: usize,
+    sort_options: SortOptions,
+    wal: Option<Wal>,
+    shadow: Option<ShadowTable>,
+    versions: Option<VersionLog>,
+    snapshots: Arc<Mutex<SnapshotRegistry>>,
+    in_transaction: bool,
+    is_read_only: bool,
+    has_changes: bool,
+    shutdown_status: ShutdownStatus,
+    refresh_interval: Option<Duration>,
+    last_refresh: Instant,
+    drop_error_hook: fn(&VirtualArrayError),
+    is_closed: bool,
 }
 
 impl<'metadata, Item, Store, PSerializer, MSerializer>
@@ -83,7 +219,129 @@ where
     PSerializer: page::Serializer<Item>,
     MSerializer: metadata::Serializer,
 {
+    pub(crate) fn new(
+        storage: Store,
+        metadata: metadata::Metadata<'metadata>,
+        page_serializer: PSerializer,
+        metadata_serializer: MSerializer,
+        buffer_size: usize,
+    ) -> Self {
+        let shutdown_status = if metadata.is_dirty {
+            ShutdownStatus::Unclean
+        } else {
+            ShutdownStatus::Clean
+        };
+
+        Self {
+            pages: Vec::with_capacity(buffer_size),
+            metadata,
+            buffer_size,
+            storage: ManuallyDrop::new(storage),
+            page_serializer,
+            metadata_serializer,
+            sort_options: SortOptions::default(),
+            wal: None,
+            shadow: None,
+            versions: None,
+            snapshots: Arc::default(),
+            in_transaction: false,
+            is_read_only: false,
+            has_changes: false,
+            shutdown_status,
+            refresh_interval: None,
+            last_refresh: Instant::now(),
+            drop_error_hook: ignore_drop_error,
+            is_closed: false,
+        }
+    }
+
+    /// Attaches a write-ahead log. When the array is opened, committed batches left in the log
+    /// by an interrupted run are applied first.
+    pub(crate) fn attach_wal(
+        &mut self,
+        storage: Box<dyn Storage + Send>,
+        should_recover: bool,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if self.shadow.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "write-ahead log cannot be used with shadow paging",
+            )
+            .into());
+        }
+
+        let mut wal = Wal::new(storage);
+
+        if should_recover {
+            let page_size = self.metadata.page_size::<Item, PSerializer>();
+            let pages_count = self.metadata.count_pages::<Item>();
+
+            wal.replay(|page_index, bytes| {
+                if page_index >= pages_count || bytes.len() != page_size {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "write-ahead log does not match the array",
+                    )
+                    .into());
+                }
+
+                self.seek_to_page(page_index)?;
+                self.storage.write_all(bytes)?;
+                Ok::<_, VirtualArrayError>(())
+            })?;
+            self.storage.sync()?;
+        }
+
+        wal.reset()?;
+        self.wal = Some(wal);
+        Ok(())
+    }
+
+    /// Switches page access to the page table of shadow paging. A new table is empty until the
+    /// pages are written with [`write_zeroed_pages`](Self::write_zeroed_pages).
+    pub(crate) fn attach_shadow(&mut self, is_new: bool) -> Result<()> {
+        let roots_start = MSerializer::get_metadata_size_in_bytes(&self.metadata) as u64;
+        let page_size = self.metadata.page_size::<Item, PSerializer>() as u64;
+
+        let shadow = if is_new {
+            ShadowTable::create(&mut *self.storage, roots_start, page_size)?
+        } else {
+            ShadowTable::open(&mut *self.storage, roots_start, page_size)?
+        };
+
+        self.shadow = Some(shadow);
+        Ok(())
+    }
+
+    /// Keeps previous page images in a version log for `retention` generations. The log of a
+    /// new array starts empty.
+    pub(crate) fn attach_versions(
+        &mut self,
+        storage: Box<dyn Storage + Send>,
+        retention: u64,
+        is_new: bool,
+    ) -> Result<()> {
+        if !self.metadata.has_page_generations() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "version history needs page generations, which the array format does not record",
+            )
+            .into());
+        }
+
+        let versions = if is_new {
+            VersionLog::create(storage, retention)?
+        } else {
+            VersionLog::open(storage, retention)?
+        };
+
+        self.versions = Some(versions);
+        Ok(())
+    }
+
     pub fn set(&mut self, element_index: usize, value: Item) -> Result<()> {
+        self.check_writable()?;
         let index_on_page = self.get_index_on_page(element_index);
         let page = self.get_page_by_element_index(element_index)?;
 
@@ -98,7 +356,33 @@ where
         Ok(page.get(index_on_page))
     }
 
+    pub fn get_mut(&mut self, element_index: usize) -> Result<Option<ElementGuard<'_, Item>>> {
+        self.check_writable()?;
+        let index_on_page = self.get_index_on_page(element_index);
+        let page = self.get_page_by_element_index(element_index)?;
+
+        Ok(ElementGuard::new(page, index_on_page))
+    }
+
+    /// Calls `f` with the [`Entry`] of an element, which can insert, modify or remove it. The
+    /// page is only marked as modified if `f` writes through the entry.
+    pub fn update<F, R>(&mut self, element_index: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(Entry<'_, Item>) -> R,
+    {
+        Ok(f(self.entry(element_index)?))
+    }
+
+    pub fn entry(&mut self, element_index: usize) -> Result<Entry<'_, Item>> {
+        self.check_writable()?;
+        let index_on_page = self.get_index_on_page(element_index);
+        let page = self.get_page_by_element_index(element_index)?;
+
+        Ok(Entry::new(page, index_on_page))
+    }
+
     pub fn delete(&mut self, element_index: usize) -> Result<()> {
+        self.check_writable()?;
         let index_on_page = self.get_index_on_page(element_index);
         let page = self.get_page_by_element_index(element_index)?;
 
@@ -106,44 +390,215 @@ where
         self.save()
     }
 
+    pub(crate) fn take(&mut self, element_index: usize) -> Result<Option<Item>> {
+        let index_on_page = self.get_index_on_page(element_index);
+        let page = self.get_page_by_element_index(element_index)?;
+
+        Ok(page.take(index_on_page))
+    }
+
+    pub(crate) fn put(&mut self, element_index: usize, value: Option<Item>) -> Result<()> {
+        let index_on_page = self.get_index_on_page(element_index);
+        let page = self.get_page_by_element_index(element_index)?;
+
+        match value {
+            Some(value) => page.set(index_on_page, value),
+            None => page.delete(index_on_page),
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.metadata.array_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn resize(&mut self, new_len: usize) -> Result<()> {
+        let old_len = self.len();
+        if new_len == old_len {
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.mark_changed()?;
+
+        self.save()?;
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let old_pages_count = self.metadata.count_pages::<Item>();
+        let new_pages_count = new_len / elements_count_on_page + 1;
+
+        if new_len > old_len {
+            // Slots behind the old end may still hold values from an earlier shrink. They are
+            // cleared and the new pages are written before the header starts to refer to them.
+            let index_on_page = self.get_index_on_page(old_len);
+            let page = self.get_page(old_len / elements_count_on_page)?;
+            for i in index_on_page..elements_count_on_page {
+                if page.get(i).is_some() {
+                    page.delete(i);
+                }
+            }
+            self.save()?;
+
+            self.write_zeroed_pages(old_pages_count..new_pages_count)?;
+            self.storage.sync()?;
+
+            self.metadata.array_size = new_len;
+            self.write_metadata()?;
+        } else {
+            self.record_versions(new_pages_count..old_pages_count)?;
+
+            // The header is shrunk first, so a failed truncation only leaves unused bytes behind.
+            self.metadata.array_size = new_len;
+            self.write_metadata()?;
+
+            self.pages.retain(|page| page.index < new_pages_count);
+            match &mut self.shadow {
+                Some(shadow) => {
+                    shadow.stage_len(new_pages_count);
+                    self.commit_staged_pages(true)?;
+                }
+                None => {
+                    for page_index in new_pages_count..old_pages_count {
+                        self.preserve_for_snapshots(page_index)?;
+                    }
+
+                    let data_end = self.page_offset(new_pages_count);
+                    match self.storage.set_len(data_end) {
+                        Err(error) if error.kind() != std::io::ErrorKind::Unsupported => {
+                            return Err(error.into())
+                        }
+                        _ => {}
+                    }
+                    self.storage.sync()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes all modified pages and ends the current generation if anything was written in it.
+    pub fn flush(&mut self) -> Result<()> {
+        self.save()?;
+        self.storage.flush()?;
+        self.end_changed_generation()
+    }
+
+    /// Writes all modified pages, syncs the storage and returns it. Unlike dropping the array,
+    /// this reports failures and marks the shutdown as clean, see
+    /// [`shutdown_status`](Self::shutdown_status). The storage is dropped if closing fails.
+    pub fn close(mut self) -> Result<Store> {
+        let result = self.checkpoint().and_then(|()| {
+            if self.metadata.is_dirty && !self.is_read_only {
+                self.metadata.is_dirty = false;
+                self.write_metadata()?;
+            }
+            Ok(())
+        });
+
+        self.is_closed = true;
+        // SAFETY: the array is closed, so the storage is neither used nor dropped again.
+        let storage = unsafe { ManuallyDrop::take(&mut self.storage) };
+
+        result.map(|()| storage)
+    }
+
+    /// Sets the function that is called with errors that happen when the array is dropped
+    /// without [`close`](Self::close). By default they are ignored.
+    pub fn set_drop_error_hook(&mut self, hook: fn(&VirtualArrayError)) {
+        self.drop_error_hook = hook;
+    }
+
+    /// Writes all modified pages, syncs the storage and empties the write-ahead log. Like
+    /// [`flush`](Self::flush), this ends the current generation if anything was written in it.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.save()?;
+        self.storage.sync()?;
+        self.end_changed_generation()?;
+
+        if let Some(wal) = &mut self.wal {
+            wal.reset()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn wal_checkpoint_threshold(&self) -> Option<u64> {
+        self.wal.as_ref().map(|wal| wal.checkpoint_threshold())
+    }
+
+    /// Sets the size in bytes the write-ahead log may grow to before it is checkpointed.
+    pub fn set_wal_checkpoint_threshold(&mut self, checkpoint_threshold: u64) {
+        if let Some(wal) = &mut self.wal {
+            wal.set_checkpoint_threshold(checkpoint_threshold);
+        }
+    }
+
     fn get_page_by_element_index(&mut self, element_index: usize) -> Result<&mut Page<Item>> {
+        if element_index >= self.len() {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index: element_index,
+                len: self.len(),
+            });
+        }
+
         let page_index = self.get_page_index(element_index);
         self.get_page(page_index)
     }
 
+    fn find_buffered_page(&mut self, page_index: usize) -> Option<&mut Page<Item>> {
+        self.pages.iter_mut().find(|page| page.index == page_index)
+    }
+
     fn get_page(&mut self, page_index: usize) -> Result<&mut Page<Item>> {
+        self.refresh_if_due()?;
+
         let buff_index = if let Some(found_page_index) =
             self.pages.iter().position(|page| page.index == page_index)
         {
             found_page_index
         } else {
             let readed_page = self.read_page(page_index)?;
-            self.insert_page(readed_page)
+            self.insert_page(readed_page)?
         };
 
         Ok(&mut self.pages[buff_index])
     }
 
     fn read_page(&mut self, page_index: usize) -> Result<Page<Item>> {
-        self.storage
-            .seek_to_page::<Item, PSerializer, MSerializer>(page_index, &self.metadata)?;
+        self.seek_to_page(page_index)?;
 
-        Ok(PSerializer::deserialize(
-            &mut self.storage,
+        Ok(page::deserialize_stored::<_, PSerializer, _>(
+            &mut *self.storage,
             page_index,
             self.metadata.count_elements_on_page::<Item>(),
+            self.metadata.has_page_generations(),
         )?)
     }
 
-    fn insert_page(&mut self, page_to_insert: Page<Item>) -> usize {
-        if self.pages.len() < self.buffer_size {
-            self.pages.push(page_to_insert);
-            self.pages.len() - 1
-        } else {
-            let max_priority_pos = self.get_buff_max_priority_pos().unwrap();
-            self.pages[max_priority_pos] = page_to_insert;
+    /// Inserts a page into the buffer, evicting another one if the buffer is full. Pages
+    /// modified in a transaction cannot be evicted, so the buffer grows past its size when
+    /// there is nothing else to evict.
+    fn insert_page(&mut self, page_to_insert: Page<Item>) -> Result<usize> {
+        match self.get_buff_max_priority_pos() {
+            Some(max_priority_pos) if self.pages.len() >= self.buffer_size => {
+                let mut evicted_page =
+                    std::mem::replace(&mut self.pages[max_priority_pos], page_to_insert);
+
+                if evicted_page.should_be_saved() {
+                    self.write_page(&mut evicted_page)?;
+                }
 
-            max_priority_pos
+                Ok(max_priority_pos)
+            }
+            _ => {
+                self.pages.push(page_to_insert);
+                Ok(self.pages.len() - 1)
+            }
         }
     }
 
@@ -151,10 +606,217 @@ where
         self.pages
             .iter()
             .enumerate()
-            .max_by(|(_, x), (_, y)| x.cmp_priorities(&y))
+            .filter(|(_, page)| !(self.in_transaction && page.should_be_saved()))
+            .max_by(|(_, x), (_, y)| x.cmp_priorities(y))
             .map(|(index, _)| index)
     }
 
+    fn get_range<R: RangeBounds<usize>>(&self, range: R) -> Result<Range<usize>> {
+        let out_of_bounds = |index| VirtualArrayError::IndexOutOfBounds {
+            index,
+            len: self.len(),
+        };
+
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1).ok_or_else(|| out_of_bounds(start))?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1).ok_or_else(|| out_of_bounds(end))?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+
+        if end > self.len() || start > end {
+            return Err(out_of_bounds(end.max(start)));
+        }
+
+        Ok(start..end)
+    }
+
+    /// Splits an element range into page indices and ranges of indices on those pages.
+    fn get_page_ranges(&self, range: Range<usize>) -> impl Iterator<Item = (usize, Range<usize>)> {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let first_page = range.start / elements_count_on_page;
+        let last_page = range.end.div_ceil(elements_count_on_page);
+
+        (first_page..last_page).map(move |page_index| {
+            let page_start = page_index * elements_count_on_page;
+            let start = range.start.max(page_start) - page_start;
+            let end = range.end.min(page_start + elements_count_on_page) - page_start;
+
+            (page_index, start..end)
+        })
+    }
+
+    /// Offset of a page in the storage, either fixed or looked up in the shadow page table.
+    fn page_offset(&self, page_index: usize) -> u64 {
+        match &self.shadow {
+            Some(shadow) => shadow.page_offset(page_index),
+            None => <Store as Storage>::get_page_offset::<Item, PSerializer, MSerializer>(
+                page_index,
+                &self.metadata,
+            ),
+        }
+    }
+
+    fn seek_to_page(&mut self, page_index: usize) -> std::io::Result<()> {
+        let offset = self.page_offset(page_index);
+        self.storage.seek(std::io::SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Offset a modified page is written to. In shadow paging mode this is a free slot.
+    fn stage_page(&mut self, page_index: usize) -> u64 {
+        match &mut self.shadow {
+            Some(shadow) => shadow.stage_page(page_index),
+            None => self.page_offset(page_index),
+        }
+    }
+
+    /// Keeps the current image of a page for the snapshots that may still read it, before the
+    /// page is overwritten in place.
+    fn preserve_for_snapshots(&mut self, page_index: usize) -> Result<()> {
+        if self.shadow.is_some() {
+            return Ok(());
+        }
+
+        let offset = self.page_offset(page_index);
+        let page_size = self.metadata.page_size::<Item, PSerializer>();
+        let mut snapshots = self.snapshots.lock().unwrap();
+
+        if snapshots.should_preserve(page_index) {
+            let mut bytes = vec![0; page_size];
+            self.storage.seek(std::io::SeekFrom::Start(offset))?;
+            self.storage.read_exact(&mut bytes)?;
+            snapshots.preserve(page_index, bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the images of pages that are about to be overwritten for the first time in the
+    /// current generation in the version log.
+    fn record_versions(&mut self, page_indices: impl IntoIterator<Item = usize>) -> Result<()> {
+        if self.versions.is_none() {
+            return Ok(());
+        }
+
+        let generation = self.metadata.generation;
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let page_size = self.metadata.page_size::<Item, PSerializer>();
+        let mut images = Vec::new();
+
+        for page_index in page_indices {
+            let mut bytes = vec![0; page_size];
+            self.seek_to_page(page_index)?;
+            self.storage.read_exact(&mut bytes)?;
+
+            let page_generation = page::deserialize_stored_generation::<Item, PSerializer, _>(
+                &mut std::io::Cursor::new(&bytes),
+                elements_count_on_page,
+            )?;
+            if page_generation < generation {
+                images.push((page_index, generation - 1, bytes));
+            }
+        }
+
+        if let Some(versions) = &mut self.versions {
+            versions.append_pages(&images)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [`VirtualArrayError::ReadOnly`] if the array was opened read-only.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.is_read_only {
+            return Err(VirtualArrayError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// Notes that the current generation has changes. The first change also marks the header
+    /// as dirty until the array is closed.
+    pub(crate) fn mark_changed(&mut self) -> Result<()> {
+        self.has_changes = true;
+        if !self.metadata.is_dirty {
+            self.metadata.is_dirty = true;
+            self.write_metadata()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn end_changed_generation(&mut self) -> Result<()> {
+        if self.has_changes {
+            self.advance_generation()?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends the current generation, recording the size of the array in versioned mode. Readers
+    /// notice the new generation in the header, see [`refresh`](Self::refresh).
+    pub(crate) fn advance_generation(&mut self) -> Result<()> {
+        // Nothing is written by a read-only array, so its generations only have to differ
+        // while it is open.
+        if self.is_read_only {
+            self.metadata.generation += 1;
+            return Ok(());
+        }
+
+        if let Some(versions) = &mut self.versions {
+            let version = Version {
+                array_size: self.metadata.array_size,
+                length: self.metadata.length,
+            };
+            versions.end_version(self.metadata.generation, version)?;
+        }
+
+        self.metadata.generation += 1;
+        self.write_metadata()?;
+        self.has_changes = false;
+        Ok(())
+    }
+
+    /// Makes staged pages visible. In place writes are visible right away.
+    fn commit_staged_pages(&mut self, is_written: bool) -> Result<()> {
+        if let Some(shadow) = &mut self.shadow {
+            if is_written {
+                let oldest_snapshot = self.snapshots.lock().unwrap().oldest_generation();
+                shadow.commit(&mut *self.storage, oldest_snapshot)?;
+            } else {
+                shadow.discard_staged();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_zeroed_pages(&mut self, page_indices: Range<usize>) -> Result<()> {
+        self.check_writable()?;
+        self.mark_changed()?;
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let has_generation = self.metadata.has_page_generations();
+
+        let result = page_indices.into_iter().try_for_each(|page_index| {
+            self.preserve_for_snapshots(page_index)?;
+            let offset = self.stage_page(page_index);
+            self.storage.seek(std::io::SeekFrom::Start(offset))?;
+            page::serialize_zeroed_stored::<Item, PSerializer, _>(
+                &mut *self.storage,
+                elements_count_on_page,
+                has_generation,
+            )?;
+            Ok(())
+        });
+
+        self.commit_staged_pages(result.is_ok())?;
+        result
+    }
+
     fn get_page_index(&self, element_index: usize) -> usize {
         element_index / self.metadata.count_elements_on_page::<Item>()
     }
@@ -163,11 +825,92 @@ where
         element_index % self.metadata.count_elements_on_page::<Item>()
     }
 
+    /// Writes the modified pages. Inside a transaction the pages are kept until it is
+    /// committed.
     fn save(&mut self) -> Result<()> {
-        for page in self.pages.iter().filter(|page| page.should_be_saved()) {
-            self.storage
-                .seek_to_page::<Item, PSerializer, MSerializer>(page.index, &self.metadata)?;
-            PSerializer::serialize(&mut self.storage, page)?;
+        if self.in_transaction {
+            return Ok(());
+        }
+
+        let mut pages = std::mem::take(&mut self.pages);
+
+        let mut modified_pages = pages
+            .iter_mut()
+            .filter(|page| page.should_be_saved())
+            .collect::<Vec<_>>();
+        let result = self.write_pages(&mut modified_pages);
+
+        if result.is_ok() {
+            pages.iter_mut().for_each(Page::mark_saved);
+        }
+
+        self.pages = pages;
+        result
+    }
+
+    fn write_metadata(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.has_changes = true;
+        self.storage.seek_to_start()?;
+        MSerializer::serialize(&mut *self.storage, &self.metadata)?;
+        self.storage.sync()?;
+        Ok(())
+    }
+
+    fn write_page(&mut self, page: &mut Page<Item>) -> Result<()> {
+        self.write_pages(&mut [page])
+    }
+
+    /// Stamps the pages with the current generation and writes them in place, or as one commit
+    /// in shadow paging mode. With a write-ahead log, the pages are logged as one batch and the
+    /// log is synced before any of them is written.
+    fn write_pages(&mut self, pages: &mut [&mut Page<Item>]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        self.check_writable()?;
+        self.mark_changed()?;
+
+        let serialized_pages = pages
+            .iter_mut()
+            .map(|page| {
+                page.set_generation(self.metadata.generation);
+                let mut bytes = Vec::new();
+                page::serialize_stored::<_, PSerializer, _>(
+                    &mut bytes,
+                    page,
+                    self.metadata.has_page_generations(),
+                )?;
+                Ok((page.index, bytes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.record_versions(serialized_pages.iter().map(|(page_index, _)| *page_index))?;
+
+        if let Some(wal) = &mut self.wal {
+            wal.append_commit(&serialized_pages)?;
+        }
+
+        let result: Result<()> = serialized_pages.iter().try_for_each(|(page_index, bytes)| {
+            self.preserve_for_snapshots(*page_index)?;
+            let offset = self.stage_page(*page_index);
+            self.storage.seek(std::io::SeekFrom::Start(offset))?;
+            self.storage.write_all(bytes)?;
+            Ok(())
+        });
+
+        self.commit_staged_pages(result.is_ok())?;
+        result?;
+
+        self.checkpoint_if_needed()
+    }
+
+    fn checkpoint_if_needed(&mut self) -> Result<()> {
+        if let Some(wal) = &mut self.wal {
+            if wal.should_checkpoint() {
+                self.storage.sync()?;
+                wal.reset()?;
+            }
         }
 
         Ok(())
@@ -183,16 +926,50 @@ where
     MSerializer: metadata::Serializer,
 {
     fn drop(&mut self) {
-        self.save().unwrap();
+        if self.is_closed {
+            return;
+        }
+
+        if cfg!(debug_assertions) && self.pages.iter().any(Page::should_be_saved) {
+            eprintln!("virtual_array: array with modified pages dropped without calling close");
+        }
+
+        if let Err(error) = self.save() {
+            (self.drop_error_hook)(&error);
+        }
+
+        // SAFETY: the storage is only taken out by `close`, which marks the array as closed.
+        unsafe { ManuallyDrop::drop(&mut self.storage) };
     }
 }
 
+fn ignore_drop_error(_: &VirtualArrayError) {}
+
 #[derive(Debug)]
 pub enum VirtualArrayError {
     MetadataSerializationError(metadata::SerializationError),
     PageSerializationError(page::SerializationError),
     ConstructMetadataError(metadata::ConstructError),
     IoError(std::io::Error),
+    IndexOutOfBounds {
+        index: usize,
+        len: usize,
+    },
+    /// The array file is locked by another process.
+    Locked,
+    /// The array was opened read-only and cannot be modified.
+    ReadOnly,
+    /// The generation is newer than the current one or older than the retained versions.
+    GenerationUnavailable {
+        generation: u64,
+        oldest: u64,
+        current: u64,
+    },
+    /// The array was created with items of a different layout than it is opened with.
+    ItemTypeMismatch {
+        expected: metadata::ItemLayout,
+        found: metadata::ItemLayout,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, VirtualArrayError>;
@@ -204,6 +981,25 @@ impl Display for VirtualArrayError {
             Self::PageSerializationError(error) => Display::fmt(&error, f),
             Self::IoError(error) => Display::fmt(&error, f),
             Self::ConstructMetadataError(error) => Display::fmt(&error, f),
+            Self::IndexOutOfBounds { index, len } => write!(
+                f,
+                "index {} is out of bounds for array of length {}",
+                index, len
+            ),
+            Self::Locked => write!(f, "array file is locked by another process"),
+            Self::ReadOnly => write!(f, "array is opened read-only"),
+            Self::GenerationUnavailable {
+                generation,
+                oldest,
+                current,
+            } => write!(
+                f,
+                "generation {} is not available, only generations {} to {} are kept",
+                generation, oldest, current
+            ),
+            Self::ItemTypeMismatch { expected, found } => {
+                write!(f, "array holds {} but is opened with {}", found, expected)
+            }
         }
     }
 }
@@ -215,6 +1011,11 @@ impl Error for VirtualArrayError {
             Self::PageSerializationError(error) => Some(error),
             Self::IoError(error) => Some(error),
             Self::ConstructMetadataError(error) => Some(error),
+            Self::IndexOutOfBounds { .. }
+            | Self::Locked
+            | Self::ReadOnly
+            | Self::GenerationUnavailable { .. }
+            | Self::ItemTypeMismatch { .. } => None,
         }
     }
 }
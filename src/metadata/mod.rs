@@ -1,14 +1,90 @@
 mod serializer;
 
 pub use serializer::*;
-use std::{error::Error, fmt::Display, mem};
+use std::{error::Error, fmt::Display, mem, num::NonZeroU64};
+
+use crate::page;
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct Metadata<'signature> {
     pub signature: &'signature [u8],
     pub data_chunk_size: usize,
     pub array_size: usize,
-    _private: (),
+    pub length: usize,
+    pub commit_mode: CommitMode,
+    /// Generation stamped on written pages. It is increased when a snapshot is taken, and when
+    /// a flush, a checkpoint or a committed transaction ends a generation with changes.
+    pub generation: u64,
+    /// Set when the first modification is written and cleared when the array is closed.
+    pub is_dirty: bool,
+    /// Feature flags a reader that does not know them may ignore.
+    pub compat_features: u64,
+    /// Feature flags a reader must know to open the array.
+    pub incompat_features: u64,
+    /// Header fields added by a newer minor format version, kept as they are when the header
+    /// is written again.
+    pub unknown_fields: Vec<u8>,
+    /// Header format major version the fields are encoded in. A header is written again in
+    /// the version it was read in, so older readers can still open the array.
+    pub format_major_version: u16,
+    pub format_minor_version: u16,
+    /// Layout of the items the array was created with. Headers written before it was recorded
+    /// do not have one.
+    pub item_layout: Option<ItemLayout>,
+}
+
+/// Size and alignment of the items of an array, and an optional hash of their type name or
+/// schema, checked when the array is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemLayout {
+    pub size: usize,
+    pub align: usize,
+    /// See [`VirtualArrayBuilder::item_schema`](crate::VirtualArrayBuilder::item_schema).
+    pub schema: Option<NonZeroU64>,
+}
+
+impl ItemLayout {
+    pub fn of<Item>(schema: Option<NonZeroU64>) -> Self {
+        Self {
+            size: mem::size_of::<Item>(),
+            align: mem::align_of::<Item>(),
+            schema,
+        }
+    }
+
+    /// Whether items stored with this layout can be read as items of `other`. Schemas are only
+    /// compared if both layouts have one.
+    pub fn matches(&self, other: &Self) -> bool {
+        let schemas_match = match (self.schema, other.schema) {
+            (Some(schema), Some(other_schema)) => schema == other_schema,
+            _ => true,
+        };
+
+        self.size == other.size && self.align == other.align && schemas_match
+    }
+}
+
+impl Display for ItemLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} byte items aligned to {}", self.size, self.align)?;
+        if let Some(schema) = self.schema {
+            write!(f, " with schema {:#x}", schema)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How modified pages reach the storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitMode {
+    /// Pages are overwritten at their fixed offsets.
+    #[default]
+    InPlace,
+    /// Pages are written to free locations and a page table is switched atomically on every
+    /// commit, see [`VirtualArrayBuilder::commit_mode`](crate::VirtualArrayBuilder::commit_mode).
+    ShadowPaging,
 }
 
 impl<'signature> Metadata<'signature> {
@@ -21,7 +97,16 @@ impl<'signature> Metadata<'signature> {
             signature,
             data_chunk_size,
             array_size,
-            _private: (),
+            length: 0,
+            commit_mode: CommitMode::InPlace,
+            generation: 1,
+            is_dirty: false,
+            compat_features: 0,
+            incompat_features: 0,
+            unknown_fields: Vec::new(),
+            format_major_version: FORMAT_MAJOR_VERSION,
+            format_minor_version: FORMAT_MINOR_VERSION,
+            item_layout: Some(ItemLayout::of::<Item>(None)),
         };
 
         if metadata.data_chunk_size == 0 {
@@ -35,9 +120,35 @@ impl<'signature> Metadata<'signature> {
         Ok(metadata)
     }
 
+    /// Whether each page is followed by the generation it was last written in. Pages of
+    /// headers without the versioned preamble do not record it.
+    pub fn has_page_generations(&self) -> bool {
+        self.format_major_version != UNVERSIONED_FORMAT_MAJOR_VERSION
+    }
+
+    pub(crate) fn page_size<Item, PSerializer: page::Serializer<Item>>(&self) -> usize {
+        page::stored_page_size::<Item, PSerializer>(
+            self.count_elements_on_page::<Item>(),
+            self.has_page_generations(),
+        )
+    }
+
     pub(crate) fn count_elements_on_page<Item>(&self) -> usize {
         self.data_chunk_size / mem::size_of::<Item>()
     }
+
+    pub(crate) fn count_pages<Item>(&self) -> usize {
+        self.array_size / self.count_elements_on_page::<Item>() + 1
+    }
+
+    /// Number of elements on the page that are below the array length.
+    pub(crate) fn count_used_on_page<Item>(&self, page_index: usize) -> usize {
+        let elements_count_on_page = self.count_elements_on_page::<Item>();
+
+        self.array_size
+            .saturating_sub(page_index * elements_count_on_page)
+            .min(elements_count_on_page)
+    }
 }
 
 #[derive(Debug)]
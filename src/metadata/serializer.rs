@@ -1,12 +1,13 @@
 use std::{
     error::Error,
     fmt::Display,
-    io::{Read, Write},
+    io::{self, Read, Write},
     mem,
+    num::NonZeroU64,
 };
 
 use crate::{
-    metadata::{ConstructError, Metadata},
+    metadata::{CommitMode, ConstructError, ItemLayout, Metadata},
     BytesCount,
 };
 
@@ -26,13 +27,49 @@ pub trait Serializer {
 
 #[derive(Debug)]
 pub enum SerializationError {
-    InvalidSignature { expected: Vec<u8>, found: Vec<u8> },
+    InvalidSignature {
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    UnknownCommitMode(usize),
+    /// The header was written by a format major version this version cannot read.
+    UnsupportedVersion {
+        major: u16,
+        minor: u16,
+    },
+    InvalidHeaderLength(usize),
+    /// A header field does not fit the `usize` of this host.
+    ValueOverflow(u64),
+    /// The array uses incompatible features this version does not know.
+    UnknownIncompatFeatures(u64),
+    /// The header format version of the array has no field for a value that was set.
+    UnsupportedField(&'static str),
     IoError(std::io::Error),
     ConstructError(ConstructError),
 }
 
 pub type SerializationResult<T> = Result<T, SerializationError>;
 
+/// Magic bytes following the signature, marking the versioned header layout.
+pub const HEADER_MAGIC: &[u8; 4] = b"VAHD";
+/// Major version of headers written before the versioned preamble. Only the page size and
+/// the array size follow the signature, as native-endian `usize` values, and pages are not
+/// followed by their generation.
+pub const UNVERSIONED_FORMAT_MAJOR_VERSION: u16 = 0;
+pub const FORMAT_MAJOR_VERSION: u16 = 2;
+/// Minor version 1 adds the item layout.
+pub const FORMAT_MINOR_VERSION: u16 = 1;
+/// Incompatible features this version knows. Arrays using any other are not opened.
+pub const SUPPORTED_INCOMPAT_FEATURES: u64 = 0;
+
+/// Size of the magic, versions, header length and feature flags.
+const PREAMBLE_SIZE: usize = HEADER_MAGIC.len() + 2 + 2 + 4 + 8 + 8;
+
+/// Writes the signature and a self-describing preamble: magic, major and minor format
+/// version, header length and compatible and incompatible feature flags. The array fields
+/// follow, and a newer minor version may append fields that older readers skip using the
+/// header length. Everything is little-endian and fields are 64 bits wide. Headers without
+/// the magic are read and written in the unversioned layout.
 #[derive(Debug)]
 pub struct DefaultSerializer;
 
@@ -41,9 +78,39 @@ impl Serializer for DefaultSerializer {
         writer: &mut Writer,
         metadata: &Metadata,
     ) -> SerializationResult<()> {
+        let major = metadata.format_major_version;
+        let minor = metadata.format_minor_version;
+        if major == UNVERSIONED_FORMAT_MAJOR_VERSION {
+            return Self::serialize_unversioned(writer, metadata);
+        }
+
+        let header_length = Self::get_metadata_size_in_bytes(metadata) as u32;
+
         writer.write_all(metadata.signature)?;
-        writer.write_all(metadata.data_chunk_size.to_ne_bytes().as_slice())?;
-        writer.write_all(metadata.array_size.to_ne_bytes().as_slice())?;
+        writer.write_all(HEADER_MAGIC)?;
+        writer.write_all(&major.to_le_bytes())?;
+        writer.write_all(&minor.to_le_bytes())?;
+        writer.write_all(&header_length.to_le_bytes())?;
+        writer.write_all(&metadata.compat_features.to_le_bytes())?;
+        writer.write_all(&metadata.incompat_features.to_le_bytes())?;
+
+        write_field(writer, major, metadata.data_chunk_size)?;
+        write_field(writer, major, metadata.array_size)?;
+        write_field(writer, major, metadata.length)?;
+        write_field(writer, major, commit_mode_value(metadata.commit_mode))?;
+        writer.write_all(&metadata.generation.to_le_bytes())?;
+        write_field(writer, major, usize::from(metadata.is_dirty))?;
+        if has_item_layout(major, minor) {
+            let layout = metadata.item_layout.unwrap_or(ItemLayout {
+                size: 0,
+                align: 0,
+                schema: None,
+            });
+            write_field(writer, major, layout.size)?;
+            write_field(writer, major, layout.align)?;
+            writer.write_all(&layout.schema.map_or(0, NonZeroU64::get).to_le_bytes())?;
+        }
+        writer.write_all(&metadata.unknown_fields)?;
 
         Ok(())
     }
@@ -67,23 +134,198 @@ impl Serializer for DefaultSerializer {
             });
         }
 
-        let mut buff = [0u8; size_of::<usize>()];
+        let mut magic = [0u8; HEADER_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != HEADER_MAGIC {
+            // The bytes are the start of the first field of an unversioned header.
+            return Self::deserialize_unversioned::<_, Item>(
+                &mut magic.as_slice().chain(reader),
+                signature,
+            );
+        }
 
-        reader.read_exact(&mut buff)?;
-        let data_chunk_size = usize::from_ne_bytes(buff);
+        let mut preamble = [0u8; PREAMBLE_SIZE - HEADER_MAGIC.len()];
+        reader.read_exact(&mut preamble)?;
+        let major = u16::from_le_bytes([preamble[0], preamble[1]]);
+        let minor = u16::from_le_bytes([preamble[2], preamble[3]]);
+        let header_length = u32::from_le_bytes(preamble[4..8].try_into().unwrap()) as usize;
+        let compat_features = u64::from_le_bytes(preamble[8..16].try_into().unwrap());
+        let incompat_features = u64::from_le_bytes(preamble[16..24].try_into().unwrap());
+
+        if major != FORMAT_MAJOR_VERSION {
+            return Err(SerializationError::UnsupportedVersion { major, minor });
+        }
+        let known_length = signature.len() + PREAMBLE_SIZE + fields_size(major, minor);
+        if header_length < known_length {
+            return Err(SerializationError::InvalidHeaderLength(header_length));
+        }
+        let unknown_incompat_features = incompat_features & !SUPPORTED_INCOMPAT_FEATURES;
+        if unknown_incompat_features != 0 {
+            return Err(SerializationError::UnknownIncompatFeatures(
+                unknown_incompat_features,
+            ));
+        }
+
+        let data_chunk_size = read_field(reader, major)?;
+        let array_size = read_field(reader, major)?;
+        let length = read_field(reader, major)?;
+        let commit_mode = read_commit_mode(reader, major)?;
 
+        let mut buff = [0u8; size_of::<u64>()];
         reader.read_exact(&mut buff)?;
-        let array_size = usize::from_ne_bytes(buff);
+        let generation = u64::from_le_bytes(buff);
 
-        let metadata = Metadata::new::<Item>(signature, data_chunk_size, array_size)?;
+        let is_dirty = read_field(reader, major)? != 0;
+
+        let mut item_layout = None;
+        if has_item_layout(major, minor) {
+            let size = read_field(reader, major)?;
+            let align = read_field(reader, major)?;
+            let mut buff = [0u8; size_of::<u64>()];
+            reader.read_exact(&mut buff)?;
+            let schema = u64::from_le_bytes(buff);
+
+            if size != 0 {
+                item_layout = Some(ItemLayout {
+                    size,
+                    align,
+                    schema: NonZeroU64::new(schema),
+                });
+            }
+        }
+
+        let mut unknown_fields = vec![0; header_length - known_length];
+        reader.read_exact(&mut unknown_fields)?;
+
+        let mut metadata = Metadata::new::<Item>(signature, data_chunk_size, array_size)?;
+        metadata.length = length;
+        metadata.commit_mode = commit_mode;
+        metadata.generation = generation;
+        metadata.is_dirty = is_dirty;
+        metadata.compat_features = compat_features;
+        metadata.incompat_features = incompat_features;
+        metadata.unknown_fields = unknown_fields;
+        metadata.format_major_version = major;
+        metadata.format_minor_version = minor;
+        metadata.item_layout = item_layout;
         Ok(metadata)
     }
 
     fn get_metadata_size_in_bytes(metadata: &Metadata) -> BytesCount {
-        mem::size_of::<u8>() * metadata.signature.len() + mem::size_of::<usize>() * 2
+        if metadata.format_major_version == UNVERSIONED_FORMAT_MAJOR_VERSION {
+            return mem::size_of_val(metadata.signature)
+                + fields_size(
+                    UNVERSIONED_FORMAT_MAJOR_VERSION,
+                    metadata.format_minor_version,
+                );
+        }
+
+        mem::size_of_val(metadata.signature)
+            + PREAMBLE_SIZE
+            + fields_size(metadata.format_major_version, metadata.format_minor_version)
+            + metadata.unknown_fields.len()
     }
 }
 
+impl DefaultSerializer {
+    /// Writes an unversioned header, which only has the page size and the array size. Other
+    /// values it has no field for are refused, except for the generation and the dirty flag,
+    /// which are not kept.
+    fn serialize_unversioned<Writer: Write>(
+        writer: &mut Writer,
+        metadata: &Metadata,
+    ) -> SerializationResult<()> {
+        if metadata.length != 0 {
+            return Err(SerializationError::UnsupportedField("length"));
+        }
+        if metadata.commit_mode != CommitMode::InPlace {
+            return Err(SerializationError::UnsupportedField("commit mode"));
+        }
+
+        writer.write_all(metadata.signature)?;
+        write_field(
+            writer,
+            UNVERSIONED_FORMAT_MAJOR_VERSION,
+            metadata.data_chunk_size,
+        )?;
+        write_field(
+            writer,
+            UNVERSIONED_FORMAT_MAJOR_VERSION,
+            metadata.array_size,
+        )?;
+
+        Ok(())
+    }
+
+    fn deserialize_unversioned<'signature, Reader: Read, Item>(
+        reader: &mut Reader,
+        signature: &'signature [u8],
+    ) -> SerializationResult<Metadata<'signature>> {
+        let data_chunk_size = read_field(reader, UNVERSIONED_FORMAT_MAJOR_VERSION)?;
+        let array_size = read_field(reader, UNVERSIONED_FORMAT_MAJOR_VERSION)?;
+
+        let mut metadata = Metadata::new::<Item>(signature, data_chunk_size, array_size)?;
+        metadata.format_major_version = UNVERSIONED_FORMAT_MAJOR_VERSION;
+        metadata.format_minor_version = 0;
+        metadata.item_layout = None;
+        Ok(metadata)
+    }
+}
+
+/// Size of the array fields in a header of the format version.
+fn fields_size(major: u16, minor: u16) -> usize {
+    if major == UNVERSIONED_FORMAT_MAJOR_VERSION {
+        mem::size_of::<usize>() * 2
+    } else if has_item_layout(major, minor) {
+        mem::size_of::<u64>() * 9
+    } else {
+        mem::size_of::<u64>() * 6
+    }
+}
+
+fn has_item_layout(major: u16, minor: u16) -> bool {
+    major == FORMAT_MAJOR_VERSION && minor >= 1
+}
+
+fn commit_mode_value(commit_mode: CommitMode) -> usize {
+    match commit_mode {
+        CommitMode::InPlace => 0,
+        CommitMode::ShadowPaging => 1,
+    }
+}
+
+fn read_commit_mode<Reader: Read>(
+    reader: &mut Reader,
+    major: u16,
+) -> SerializationResult<CommitMode> {
+    match read_field(reader, major)? {
+        0 => Ok(CommitMode::InPlace),
+        1 => Ok(CommitMode::ShadowPaging),
+        value => Err(SerializationError::UnknownCommitMode(value)),
+    }
+}
+
+fn write_field<Writer: Write>(writer: &mut Writer, major: u16, value: usize) -> io::Result<()> {
+    if major == UNVERSIONED_FORMAT_MAJOR_VERSION {
+        writer.write_all(&value.to_ne_bytes())
+    } else {
+        writer.write_all(&(value as u64).to_le_bytes())
+    }
+}
+
+fn read_field<Reader: Read>(reader: &mut Reader, major: u16) -> SerializationResult<usize> {
+    if major == UNVERSIONED_FORMAT_MAJOR_VERSION {
+        let mut buff = [0u8; mem::size_of::<usize>()];
+        reader.read_exact(&mut buff)?;
+        return Ok(usize::from_ne_bytes(buff));
+    }
+
+    let mut buff = [0u8; mem::size_of::<u64>()];
+    reader.read_exact(&mut buff)?;
+    let value = u64::from_le_bytes(buff);
+    usize::try_from(value).map_err(|_| SerializationError::ValueOverflow(value))
+}
+
 impl From<std::io::Error> for SerializationError {
     fn from(io_error: std::io::Error) -> Self {
         Self::IoError(io_error)
@@ -104,6 +346,24 @@ impl Display for SerializationError {
                 "invalid signature value (expected: {:?}, found: {:?})",
                 expected, found
             ),
+            Self::UnknownCommitMode(value) => write!(f, "unknown commit mode {}", value),
+            Self::UnsupportedVersion { major, minor } => {
+                write!(f, "unsupported header format version {}.{}", major, minor)
+            }
+            Self::InvalidHeaderLength(length) => write!(f, "invalid header length {}", length),
+            Self::ValueOverflow(value) => {
+                write!(
+                    f,
+                    "header value {} does not fit the size of this host",
+                    value
+                )
+            }
+            Self::UnknownIncompatFeatures(features) => {
+                write!(f, "unknown incompatible features {:#x}", features)
+            }
+            Self::UnsupportedField(field) => {
+                write!(f, "header format version cannot store the {}", field)
+            }
             Self::IoError(io_error) => io_error.fmt(f),
             Self::ConstructError(construct_error) => construct_error.fmt(f),
         }
@@ -114,6 +374,12 @@ impl Error for SerializationError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             Self::InvalidSignature { .. } => None,
+            Self::UnknownCommitMode(_) => None,
+            Self::UnsupportedVersion { .. } => None,
+            Self::InvalidHeaderLength(_) => None,
+            Self::ValueOverflow(_) => None,
+            Self::UnknownIncompatFeatures(_) => None,
+            Self::UnsupportedField(_) => None,
             Self::ConstructError(_) => None,
             Self::IoError(io_error) => Some(io_error),
         }
@@ -0,0 +1,112 @@
+use std::ops::{Range, RangeBounds};
+
+use crate::{metadata, page, page::Page, Result, Storage, VirtualArray, VirtualArrayError};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Swaps two elements. Both indices are checked before anything is changed, and `a` is
+    /// put back if `b` cannot be read.
+    pub fn swap(&mut self, a: usize, b: usize) -> Result<()> {
+        self.check_writable()?;
+        if let Some(index) = [a, b].into_iter().find(|&index| index >= self.len()) {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index,
+                len: self.len(),
+            });
+        }
+
+        let value_a = self.take(a)?;
+        let value_b = match self.take(b) {
+            Ok(value_b) => value_b,
+            Err(error) => {
+                self.put(a, value_a)?;
+                return Err(error);
+            }
+        };
+
+        self.put(a, value_b)?;
+        self.put(b, value_a)?;
+        self.save()
+    }
+
+    /// Copies elements of `src` to the range starting at `dest`. The ranges may overlap.
+    /// Absent elements are copied as absent.
+    pub fn copy_within<R: RangeBounds<usize>>(&mut self, src: R, dest: usize) -> Result<()>
+    where
+        Item: Clone,
+    {
+        self.check_writable()?;
+        let src = self.get_range(src)?;
+        self.transfer(src, dest, |page, index_on_page| {
+            page.get(index_on_page).cloned()
+        })
+    }
+
+    /// Moves elements of `src` to the range starting at `dest`. The ranges may overlap, slots
+    /// of `src` that are not overwritten become absent.
+    pub fn move_range<R: RangeBounds<usize>>(&mut self, src: R, dest: usize) -> Result<()> {
+        self.check_writable()?;
+        let src = self.get_range(src)?;
+        self.transfer(src, dest, |page, index_on_page| page.take(index_on_page))
+    }
+
+    /// Transfers elements in segments that never cross a page boundary on either side, so at
+    /// most one page worth of elements is held outside of the buffer. The direction is chosen
+    /// so that overlapping elements are read before they are overwritten.
+    fn transfer<F>(&mut self, src: Range<usize>, dest: usize, mut read: F) -> Result<()>
+    where
+        F: FnMut(&mut Page<Item>, usize) -> Option<Item>,
+    {
+        let count = src.len();
+        if dest.checked_add(count).is_none_or(|end| end > self.len()) {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index: dest.saturating_add(count),
+                len: self.len(),
+            });
+        }
+        if count == 0 || src.start == dest {
+            return Ok(());
+        }
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let space_after = |index: usize| elements_count_on_page - index % elements_count_on_page;
+        let space_before = |index: usize| index % elements_count_on_page + 1;
+
+        let mut segment = Vec::with_capacity(elements_count_on_page.min(count));
+        let mut done = 0;
+
+        while done < count {
+            let offsets = if dest < src.start {
+                let start = done;
+                let len = (count - done)
+                    .min(space_after(src.start + start))
+                    .min(space_after(dest + start));
+                start..start + len
+            } else {
+                let end = count - done;
+                let len = end
+                    .min(space_before(src.start + end - 1))
+                    .min(space_before(dest + end - 1));
+                end - len..end
+            };
+
+            let page = self.get_page_by_element_index(src.start + offsets.start)?;
+            let first_index_on_page = (src.start + offsets.start) % elements_count_on_page;
+            segment.extend((0..offsets.len()).map(|i| read(page, first_index_on_page + i)));
+
+            for (offset, value) in offsets.clone().zip(segment.drain(..)) {
+                self.put(dest + offset, value)?;
+            }
+
+            done += offsets.len();
+        }
+
+        self.save()
+    }
+}
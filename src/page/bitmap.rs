@@ -1,11 +1,16 @@
+use std::ops::Range;
+
 use crate::BytesCount;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Bitmap {
     elements_count: usize,
     bytes: Vec<u8>,
 }
 
+const WORD_BYTES: usize = 8;
+const WORD_BITS: usize = 8 * WORD_BYTES;
+
 struct Indices {
     byte: usize,
     bit: usize,
@@ -24,7 +29,7 @@ impl Bitmap {
     pub(super) fn calc_bitmap_size(count_of_elements: usize) -> BytesCount {
         let count_of_bytes = count_of_elements / 8;
 
-        if count_of_elements % 8 != 0 {
+        if !count_of_elements.is_multiple_of(8) {
             count_of_bytes + 1
         } else {
             count_of_bytes
@@ -53,6 +58,28 @@ impl Bitmap {
         };
     }
 
+    pub(super) fn set_range(&mut self, range: Range<usize>, value: bool) {
+        debug_assert!(range.end <= self.elements_count);
+
+        let mut index = range.start;
+        while index < range.end {
+            let Indices {
+                byte: byte_index,
+                bit: bit_index,
+            } = self.get_indices(index);
+            let bits_count = (8 - bit_index).min(range.end - index);
+            let mask = (((1u16 << bits_count) - 1) << bit_index) as u8;
+
+            self.bytes[byte_index] = if value {
+                self.bytes[byte_index] | mask
+            } else {
+                self.bytes[byte_index] & !mask
+            };
+
+            index += bits_count;
+        }
+    }
+
     pub(super) fn get(&self, index: usize) -> bool {
         debug_assert!(index < self.elements_count);
 
@@ -64,6 +91,112 @@ impl Bitmap {
         self.bytes[byte_index] & (1 << bit_index) != 0
     }
 
+    pub fn len(&self) -> usize {
+        self.elements_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements_count == 0
+    }
+
+    pub fn first_set(&self) -> Option<usize> {
+        self.next_set_from(0)
+    }
+
+    pub fn first_clear(&self) -> Option<usize> {
+        self.next_clear_from(0)
+    }
+
+    /// Returns the first set index that is greater than or equal to `from`.
+    pub fn next_set_from(&self, from: usize) -> Option<usize> {
+        self.find_next(from, |word| word)
+    }
+
+    /// Returns the first clear index that is greater than or equal to `from`.
+    pub fn next_clear_from(&self, from: usize) -> Option<usize> {
+        self.find_next(from, |word| !word)
+    }
+
+    /// Returns the last set index that is less than or equal to `from`.
+    pub fn prev_set_from(&self, from: usize) -> Option<usize> {
+        if self.elements_count == 0 {
+            return None;
+        }
+
+        let from = from.min(self.elements_count - 1);
+        let mut word_index = from / WORD_BITS;
+        let mut word = self.get_word(word_index) & (!0 >> (WORD_BITS - 1 - from % WORD_BITS));
+
+        loop {
+            if word != 0 {
+                return Some(
+                    word_index * WORD_BITS + WORD_BITS - 1 - word.leading_zeros() as usize,
+                );
+            }
+            if word_index == 0 {
+                return None;
+            }
+
+            word_index -= 1;
+            word = self.get_word(word_index);
+        }
+    }
+
+    pub fn count_ones(&self) -> usize {
+        (0..self.count_words())
+            .map(|word_index| self.get_word(word_index).count_ones() as usize)
+            .sum()
+    }
+
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.first_set(), |&index| self.next_set_from(index + 1))
+    }
+
+    fn find_next(&self, from: usize, map_word: impl Fn(u64) -> u64) -> Option<usize> {
+        if from >= self.elements_count {
+            return None;
+        }
+
+        let mut word_index = from / WORD_BITS;
+        let mut word = map_word(self.get_word(word_index)) & (!0 << (from % WORD_BITS));
+
+        loop {
+            if word != 0 {
+                let index = word_index * WORD_BITS + word.trailing_zeros() as usize;
+                return (index < self.elements_count).then_some(index);
+            }
+
+            word_index += 1;
+            if word_index >= self.count_words() {
+                return None;
+            }
+            word = map_word(self.get_word(word_index));
+        }
+    }
+
+    fn count_words(&self) -> usize {
+        self.bytes.len().div_ceil(WORD_BYTES)
+    }
+
+    /// Reads up to eight bytes of the bitmap as a little-endian word, so that bit `i` of the
+    /// word corresponds to the element `word_index * 64 + i`. Bits past the last element are
+    /// always zero.
+    fn get_word(&self, word_index: usize) -> u64 {
+        let start = word_index * WORD_BYTES;
+        let end = (start + WORD_BYTES).min(self.bytes.len());
+
+        let mut buffer = [0u8; WORD_BYTES];
+        buffer[..end - start].copy_from_slice(&self.bytes[start..end]);
+        let word = u64::from_le_bytes(buffer);
+
+        let bits_left = self.elements_count - word_index * WORD_BITS;
+        if bits_left < WORD_BITS {
+            word & ((1 << bits_left) - 1)
+        } else {
+            word
+        }
+    }
+
     fn get_indices(&self, index: usize) -> Indices {
         Indices {
             byte: index / 8,
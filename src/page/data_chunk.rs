@@ -25,4 +25,9 @@ impl<Item> DataChunk<Item> {
         debug_assert!(index_on_page < self.source.len());
         self.source.get(index_on_page).unwrap()
     }
+
+    pub(super) fn get_mut(&mut self, index_on_page: usize) -> &mut Item {
+        debug_assert!(index_on_page < self.source.len());
+        self.source.get_mut(index_on_page).unwrap()
+    }
 }
@@ -4,13 +4,21 @@ mod serializer;
 
 pub use self::{bitmap::Bitmap, data_chunk::DataChunk, serializer::*};
 
-use std::{error::Error, fmt::Display, time::SystemTime};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+    time::SystemTime,
+};
 
 #[derive(Debug)]
 pub struct Page<Item> {
     pub bitmap: Bitmap,
     pub data_chunk: DataChunk<Item>,
     pub(crate) index: usize,
+    generation: u64,
     handling_time: SystemTime,
     is_modified: bool,
 }
@@ -40,18 +48,71 @@ impl<Item> Page<Item> {
             handling_time: SystemTime::now(),
             is_modified: false,
             index,
+            generation: 0,
         })
     }
 
+    pub(crate) fn empty(index: usize, elements_count: usize) -> Self
+    where
+        Item: Default,
+    {
+        let bitmap = Bitmap::new(
+            elements_count,
+            vec![0; Bitmap::calc_bitmap_size(elements_count)],
+        );
+        let data_chunk = DataChunk::from(
+            (0..elements_count)
+                .map(|_| Item::default())
+                .collect::<Vec<_>>(),
+        );
+
+        Self {
+            bitmap,
+            data_chunk,
+            handling_time: SystemTime::now(),
+            is_modified: true,
+            index,
+            generation: 0,
+        }
+    }
+
     pub(crate) fn set(&mut self, index: usize, value: Item) {
-        self.is_modified = true;
-        self.handling_time = SystemTime::now();
+        self.mark_modified();
 
         self.data_chunk.set(index, value);
         self.bitmap.set(index, true);
     }
 
-    pub(crate) fn get(&self, index: usize) -> Option<&Item> {
+    /// Generation of the array when the page was last written, or 0 if it never was.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Sets the generation of the array the page was last written in.
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn len(&self) -> usize {
+        self.data_chunk.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the indices on the page and the values of the present elements.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Item)> {
+        self.bitmap
+            .iter_ones()
+            .map(|index| (index, self.data_chunk.get(index)))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Item> {
         if !self.bitmap.get(index) {
             None
         } else {
@@ -59,10 +120,55 @@ impl<Item> Page<Item> {
         }
     }
 
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut Item> {
+        if !self.bitmap.get(index) {
+            None
+        } else {
+            Some(self.data_chunk.get_mut(index))
+        }
+    }
+
     pub(crate) fn delete(&mut self, index: usize) {
+        self.mark_modified();
+        self.bitmap.set(index, false);
+    }
+
+    pub(crate) fn take(&mut self, index: usize) -> Option<Item>
+    where
+        Item: Default,
+    {
+        if !self.bitmap.get(index) {
+            return None;
+        }
+
+        self.delete(index);
+        Some(std::mem::take(self.data_chunk.get_mut(index)))
+    }
+
+    pub(crate) fn fill(&mut self, range: Range<usize>, value: Item)
+    where
+        Item: Clone,
+    {
+        self.mark_modified();
+
+        for index in range.clone() {
+            self.data_chunk.set(index, value.clone());
+        }
+        self.bitmap.set_range(range, true);
+    }
+
+    pub(crate) fn clear(&mut self, range: Range<usize>) {
+        self.mark_modified();
+        self.bitmap.set_range(range, false);
+    }
+
+    pub(crate) fn mark_modified(&mut self) {
         self.is_modified = true;
         self.handling_time = SystemTime::now();
-        self.bitmap.set(index, false);
+    }
+
+    pub(crate) fn mark_saved(&mut self) {
+        self.is_modified = false;
     }
 
     pub(crate) fn cmp_priorities(&self, other: &Page<Item>) -> std::cmp::Ordering {
@@ -74,6 +180,100 @@ impl<Item> Page<Item> {
     }
 }
 
+/// Size of a stored page, which is followed by its generation if `has_generation` is set. See
+/// [`Metadata::has_page_generations`](crate::metadata::Metadata::has_page_generations).
+pub(crate) fn stored_page_size<Item, PSerializer: Serializer<Item>>(
+    elements_count_on_page: usize,
+    has_generation: bool,
+) -> usize {
+    let generation_size = if has_generation {
+        mem::size_of::<u64>()
+    } else {
+        0
+    };
+
+    PSerializer::get_page_size_in_bytes(elements_count_on_page) + generation_size
+}
+
+/// Writes a page, followed by its generation if `has_generation` is set.
+pub(crate) fn serialize_stored<Item, PSerializer, Writer>(
+    writer: &mut Writer,
+    page: &Page<Item>,
+    has_generation: bool,
+) -> SerializationResult<()>
+where
+    PSerializer: Serializer<Item>,
+    Writer: Write,
+{
+    PSerializer::serialize(writer, page)?;
+    if has_generation {
+        writer.write_all(&page.generation.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Writes an empty page that was never written, followed by generation 0 if `has_generation`
+/// is set.
+pub(crate) fn serialize_zeroed_stored<Item, PSerializer, Writer>(
+    writer: &mut Writer,
+    elements_count_on_page: usize,
+    has_generation: bool,
+) -> SerializationResult<()>
+where
+    PSerializer: Serializer<Item>,
+    Writer: Write,
+{
+    PSerializer::serialize_zeroed(writer, elements_count_on_page)?;
+    if has_generation {
+        writer.write_all(&0u64.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Reads a page together with its generation if `has_generation` is set. Otherwise the page
+/// has generation 0.
+pub(crate) fn deserialize_stored<Item, PSerializer, Reader>(
+    reader: &mut Reader,
+    page_index: usize,
+    elements_count_on_page: usize,
+    has_generation: bool,
+) -> SerializationResult<Page<Item>>
+where
+    PSerializer: Serializer<Item>,
+    Reader: Read,
+{
+    let mut page = PSerializer::deserialize(reader, page_index, elements_count_on_page)?;
+    if has_generation {
+        page.generation = read_generation(reader)?;
+    }
+
+    Ok(page)
+}
+
+/// Reads only the generation of the stored page the reader is positioned at. Only pages that
+/// are followed by their generation can be passed.
+pub(crate) fn deserialize_stored_generation<Item, PSerializer, Reader>(
+    reader: &mut Reader,
+    elements_count_on_page: usize,
+) -> SerializationResult<u64>
+where
+    PSerializer: Serializer<Item>,
+    Reader: Read + Seek,
+{
+    let page_size = PSerializer::get_page_size_in_bytes(elements_count_on_page);
+    reader.seek(SeekFrom::Current(page_size as i64))?;
+
+    read_generation(reader)
+}
+
+fn read_generation<Reader: Read>(reader: &mut Reader) -> SerializationResult<u64> {
+    let mut buffer = [0; mem::size_of::<u64>()];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
 impl Display for PageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
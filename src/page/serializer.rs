@@ -1,7 +1,7 @@
 use std::{
     error::Error,
     fmt::Display,
-    io::{Read, Write},
+    io::{Read, Seek, SeekFrom, Write},
     mem, slice,
 };
 
@@ -21,6 +21,15 @@ pub trait Serializer<Item> {
         count_of_elements_on_page: usize,
     ) -> SerializationResult<Page<Item>>;
 
+    /// Reads only the bitmap of the page the reader is positioned at. The default
+    /// implementation deserializes the whole page.
+    fn deserialize_bitmap<Reader: Read + Seek>(
+        reader: &mut Reader,
+        count_of_elements_on_page: usize,
+    ) -> SerializationResult<Bitmap> {
+        Ok(Self::deserialize(reader, 0, count_of_elements_on_page)?.bitmap)
+    }
+
     fn get_page_size_in_bytes(count_of_elements_on_page: usize) -> usize;
 }
 
@@ -65,11 +74,21 @@ impl<Item: Default> Serializer<Item> for DefaultSerializer {
         elements_count_on_page: usize,
     ) -> SerializationResult<Page<Item>> {
         let data_chunk = Self::deserialize_data_chunk(reader, elements_count_on_page)?;
-        let bitmap = Self::deserialize_bitmap(reader, elements_count_on_page)?;
+        let bitmap = Self::read_bitmap(reader, elements_count_on_page)?;
 
         Ok(Page::new(page_index, bitmap, data_chunk)?)
     }
 
+    fn deserialize_bitmap<Reader: Read + Seek>(
+        reader: &mut Reader,
+        elements_count_on_page: usize,
+    ) -> SerializationResult<Bitmap> {
+        let data_chunk_size = elements_count_on_page * mem::size_of::<Item>();
+        reader.seek(SeekFrom::Current(data_chunk_size as i64))?;
+
+        Self::read_bitmap(reader, elements_count_on_page)
+    }
+
     fn get_page_size_in_bytes(elements_count_on_page: usize) -> usize {
         Bitmap::calc_bitmap_size(elements_count_on_page)
             + mem::size_of::<Item>() * elements_count_on_page
@@ -91,7 +110,7 @@ impl DefaultSerializer {
         Ok(DataChunk::from(items))
     }
 
-    fn deserialize_bitmap<Reader>(
+    fn read_bitmap<Reader>(
         reader: &mut Reader,
         elements_count_on_page: usize,
     ) -> SerializationResult<Bitmap>
@@ -103,7 +122,10 @@ impl DefaultSerializer {
         Ok(Bitmap::new(elements_count_on_page, buffer))
     }
 
-    fn convert_bytes_to_items<Item>(bytes: Vec<u8>, elements_count_on_page: usize) -> Vec<Item> {
+    pub(crate) fn convert_bytes_to_items<Item>(
+        bytes: Vec<u8>,
+        elements_count_on_page: usize,
+    ) -> Vec<Item> {
         assert_eq!(bytes.len() % mem::size_of::<Item>(), 0);
         assert_eq!(bytes.capacity() % mem::size_of::<Item>(), 0);
 
@@ -123,15 +145,12 @@ impl DefaultSerializer {
         Vec::from_raw_parts(items, len, capacity)
     }
 
-    fn convert_items_to_bytes<Item>(items: &[Item]) -> &[u8] {
+    pub(crate) fn convert_items_to_bytes<Item>(items: &[Item]) -> &[u8] {
         unsafe { Self::unchecked_convert_items_to_bytes(items) }
     }
 
     unsafe fn unchecked_convert_items_to_bytes<Item>(items: &[Item]) -> &[u8] {
-        slice::from_raw_parts(
-            items.as_ptr() as *const u8,
-            mem::size_of::<Item>() * items.len(),
-        )
+        slice::from_raw_parts(items.as_ptr() as *const u8, mem::size_of_val(items))
     }
 }
 
@@ -0,0 +1,317 @@
+use std::{io::Cursor, marker::PhantomData, ops::RangeBounds, sync::Mutex, thread};
+
+use crate::{
+    metadata::{self, Metadata},
+    page,
+    page::Page,
+    shadow::ShadowTable,
+    snapshot::SnapshotRegistry,
+    versions::VersionLog,
+    wal::Wal,
+    PositionalStorage, Result, Storage, VirtualArray,
+};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default + Send,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Calls `f` for every page on `threads` workers. Each worker reads its own disjoint range
+    /// of pages with positional reads and does not go through the page buffer. Elements past
+    /// the array length are never reported as present.
+    pub fn par_for_each_page<F>(&mut self, threads: usize, f: F) -> Result<()>
+    where
+        F: Fn(&Page<Item>) + Sync,
+    {
+        self.par_map_reduce(threads, |page| f(page), |_, _| ())?;
+        Ok(())
+    }
+
+    /// Maps every page to a value on `threads` workers and combines the values with `reduce`.
+    /// Returns `None` if the array has no pages. The order in which values are combined is not
+    /// specified.
+    pub fn par_map_reduce<T, M, R>(
+        &mut self,
+        threads: usize,
+        map: M,
+        reduce: R,
+    ) -> Result<Option<T>>
+    where
+        T: Send,
+        M: Fn(&Page<Item>) -> T + Sync,
+        R: Fn(T, T) -> T + Sync,
+    {
+        self.save()?;
+
+        let page_indices = (0..self.count_used_pages()).collect::<Vec<_>>();
+        let io = self.positional_io();
+        let results = par_process(threads, &page_indices, |page_indices| {
+            let mut result = None;
+
+            for &page_index in page_indices {
+                let page = io.read_page(page_index)?;
+                let value = map(&page);
+
+                result = Some(match result {
+                    None => value,
+                    Some(result) => reduce(result, value),
+                });
+            }
+
+            Ok(result)
+        })?;
+
+        Ok(results.into_iter().flatten().reduce(reduce))
+    }
+
+    /// Fills `range` with `value` on `threads` workers, each of them owning a disjoint range of
+    /// pages. Fully covered pages are written without being read.
+    pub fn par_fill<R>(&mut self, threads: usize, range: R, value: Item) -> Result<()>
+    where
+        R: RangeBounds<usize>,
+        Item: Clone + Sync,
+    {
+        self.check_writable()?;
+        self.mark_changed()?;
+        let range = self.get_range(range)?;
+        self.save()?;
+        self.pages.clear();
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let page_ranges = self.get_page_ranges(range).collect::<Vec<_>>();
+        let io = self.positional_io();
+
+        let result = par_process(threads, &page_ranges, |page_ranges| {
+            for (page_index, range_on_page) in page_ranges.iter().cloned() {
+                let used_on_page = io.metadata.count_used_on_page::<Item>(page_index);
+
+                let mut page = if range_on_page == (0..used_on_page) {
+                    Page::empty(page_index, elements_count_on_page)
+                } else {
+                    io.read_page(page_index)?
+                };
+
+                page.fill(range_on_page, value.clone());
+                io.write_page(&mut page)?;
+            }
+
+            Ok(())
+        });
+
+        self.commit_staged_pages(result.is_ok())?;
+        result?;
+        self.checkpoint_if_needed()
+    }
+
+    /// Calls `f` with the index and the value of every element on `threads` workers, each of
+    /// them owning a disjoint range of pages. Setting the value to `None` deletes the element,
+    /// setting it to `Some` inserts or replaces it.
+    pub fn par_update<F>(&mut self, threads: usize, f: F) -> Result<()>
+    where
+        F: Fn(usize, &mut Option<Item>) + Sync,
+    {
+        self.check_writable()?;
+        self.mark_changed()?;
+        self.save()?;
+        self.pages.clear();
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let page_indices = (0..self.count_used_pages()).collect::<Vec<_>>();
+        let io = self.positional_io();
+
+        let result = par_process(threads, &page_indices, |page_indices| {
+            for &page_index in page_indices {
+                let mut page = io.read_page(page_index)?;
+                let page_start = page_index * elements_count_on_page;
+
+                for index_on_page in 0..io.metadata.count_used_on_page::<Item>(page_index) {
+                    let mut value = page.take(index_on_page);
+                    f(page_start + index_on_page, &mut value);
+
+                    match value {
+                        Some(value) => page.set(index_on_page, value),
+                        None => page.delete(index_on_page),
+                    }
+                }
+
+                io.write_page(&mut page)?;
+            }
+
+            Ok(())
+        });
+
+        self.commit_staged_pages(result.is_ok())?;
+        result?;
+        self.checkpoint_if_needed()
+    }
+
+    fn positional_io(
+        &mut self,
+    ) -> PositionalIo<'_, 'metadata, Item, Store, PSerializer, MSerializer> {
+        PositionalIo {
+            storage: &self.storage,
+            metadata: &self.metadata,
+            wal: self.wal.as_mut().map(Mutex::new),
+            shadow: self.shadow.as_mut().map(Mutex::new),
+            versions: self.versions.as_mut().map(Mutex::new),
+            snapshots: &self.snapshots,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Splits `tasks` into at most `threads` contiguous chunks and runs `worker` on each chunk
+/// in a scoped thread.
+fn par_process<Task, T, W>(threads: usize, tasks: &[Task], worker: W) -> Result<Vec<T>>
+where
+    Task: Sync,
+    T: Send,
+    W: Fn(&[Task]) -> Result<T> + Sync,
+{
+    if tasks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let chunk_size = tasks.len().div_ceil(threads.max(1));
+    let worker = &worker;
+
+    thread::scope(|scope| {
+        let handles = tasks
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || worker(chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+/// Positional access to the pages of an array that can be shared between worker threads.
+struct PositionalIo<'array, 'metadata, Item, Store, PSerializer, MSerializer> {
+    storage: &'array Store,
+    metadata: &'array Metadata<'metadata>,
+    wal: Option<Mutex<&'array mut Wal>>,
+    shadow: Option<Mutex<&'array mut ShadowTable>>,
+    versions: Option<Mutex<&'array mut VersionLog>>,
+    snapshots: &'array Mutex<SnapshotRegistry>,
+    _marker: Marker<Item, PSerializer, MSerializer>,
+}
+
+type Marker<Item, PSerializer, MSerializer> = PhantomData<fn() -> (Item, PSerializer, MSerializer)>;
+
+impl<Item, Store, PSerializer, MSerializer>
+    PositionalIo<'_, '_, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Reads a page, clearing the flags of elements past the array length.
+    fn read_page(&self, page_index: usize) -> Result<Page<Item>> {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let mut buffer = vec![0; self.metadata.page_size::<Item, PSerializer>()];
+        self.storage
+            .read_exact_at(&mut buffer, self.page_offset(page_index))?;
+
+        let mut page = page::deserialize_stored::<_, PSerializer, _>(
+            &mut buffer.as_slice(),
+            page_index,
+            elements_count_on_page,
+            self.metadata.has_page_generations(),
+        )?;
+
+        let used_on_page = self.metadata.count_used_on_page::<Item>(page_index);
+        if used_on_page < elements_count_on_page {
+            page.clear(used_on_page..elements_count_on_page);
+        }
+
+        Ok(page)
+    }
+
+    fn page_offset(&self, page_index: usize) -> u64 {
+        match &self.shadow {
+            Some(shadow) => shadow.lock().unwrap().page_offset(page_index),
+            None => <Store as Storage>::get_page_offset::<Item, PSerializer, MSerializer>(
+                page_index,
+                self.metadata,
+            ),
+        }
+    }
+
+    /// Keeps the image of a page in the version log if it is about to be overwritten for the
+    /// first time in the current generation.
+    fn record_version(&self, page_index: usize, page_size: usize) -> Result<()> {
+        let Some(versions) = &self.versions else {
+            return Ok(());
+        };
+
+        let mut bytes = vec![0; page_size];
+        self.storage
+            .read_exact_at(&mut bytes, self.page_offset(page_index))?;
+        let page_generation = page::deserialize_stored_generation::<Item, PSerializer, _>(
+            &mut Cursor::new(&bytes),
+            self.metadata.count_elements_on_page::<Item>(),
+        )?;
+
+        let generation = self.metadata.generation;
+        if page_generation < generation {
+            versions
+                .lock()
+                .unwrap()
+                .append_pages(&[(page_index, generation - 1, bytes)])?;
+        }
+        Ok(())
+    }
+
+    /// Writes a page in place, logging it first if the array has a write-ahead log. In shadow
+    /// paging mode the page is staged in a free slot and committed after all workers finish.
+    fn write_page(&self, page: &mut Page<Item>) -> Result<()> {
+        page.set_generation(self.metadata.generation);
+        let mut buffer = Vec::new();
+        page::serialize_stored::<_, PSerializer, _>(
+            &mut buffer,
+            page,
+            self.metadata.has_page_generations(),
+        )?;
+
+        self.record_version(page.index, buffer.len())?;
+
+        if let Some(wal) = &self.wal {
+            wal.lock()
+                .unwrap()
+                .append_commit(&[(page.index, buffer.as_slice())])?;
+        }
+
+        let offset = match &self.shadow {
+            Some(shadow) => shadow.lock().unwrap().stage_page(page.index),
+            None => {
+                let offset = <Store as Storage>::get_page_offset::<Item, PSerializer, MSerializer>(
+                    page.index,
+                    self.metadata,
+                );
+
+                let mut snapshots = self.snapshots.lock().unwrap();
+                if snapshots.should_preserve(page.index) {
+                    let mut bytes = vec![0; buffer.len()];
+                    self.storage.read_exact_at(&mut bytes, offset)?;
+                    snapshots.preserve(page.index, bytes);
+                }
+
+                offset
+            }
+        };
+        self.storage.write_all_at(&buffer, offset)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    io::{self, ErrorKind},
+    time::{Duration, Instant},
+};
+
+use crate::{metadata, page, Result, Storage, VirtualArray};
+
+/// How many times the header is read before a refresh gives up on a header that keeps
+/// changing.
+const HEADER_READ_ATTEMPTS: usize = 16;
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Picks up changes flushed by another process since the header was read. The header is
+    /// read again if its generation differs, and buffered pages are dropped unless their
+    /// generation shows that they are unchanged. Returns whether the generation changed.
+    /// Arrays whose pages do not record their generation are read again on every refresh.
+    ///
+    /// Only read-only arrays are refreshed, as an array open for writing is not written by
+    /// anyone else. The array has to be opened with
+    /// [`LockBehavior::Unlocked`](crate::LockBehavior::Unlocked), as the writer holds an
+    /// exclusive lock on the file otherwise.
+    pub fn refresh(&mut self) -> Result<bool> {
+        if !self.is_read_only {
+            return Ok(false);
+        }
+        self.last_refresh = Instant::now();
+
+        let metadata = self.read_settled_header()?;
+        if metadata.has_page_generations() && metadata.generation == self.metadata.generation {
+            return Ok(false);
+        }
+
+        if metadata.data_chunk_size != self.metadata.data_chunk_size
+            || metadata.commit_mode != self.metadata.commit_mode
+            || metadata.has_page_generations() != self.metadata.has_page_generations()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "array was recreated with a different layout",
+            )
+            .into());
+        }
+
+        // Pages of the generation the header had are still being written, earlier ones are
+        // final.
+        let read_generation = self.metadata.generation;
+        self.metadata = metadata;
+        if self.shadow.is_some() {
+            self.attach_shadow(false)?;
+        }
+
+        // Read-only arrays have no modified pages, so any page can be dropped.
+        let pages_count = self.metadata.count_pages::<Item>();
+        let has_page_generations = self.metadata.has_page_generations();
+        for page in std::mem::take(&mut self.pages) {
+            if has_page_generations
+                && page.index < pages_count
+                && page.generation() < read_generation
+                && self.read_page_generation(page.index)? == page.generation()
+            {
+                self.pages.push(page);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval
+    }
+
+    /// Makes page reads [`refresh`](Self::refresh) the array once `interval` has passed since
+    /// the last refresh. `None` leaves refreshing to the caller.
+    pub fn set_refresh_interval(&mut self, interval: Option<Duration>) {
+        self.refresh_interval = interval;
+    }
+
+    pub(crate) fn refresh_if_due(&mut self) -> Result<()> {
+        if self
+            .refresh_interval
+            .is_some_and(|interval| self.last_refresh.elapsed() >= interval)
+        {
+            self.refresh()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the header while another process may be overwriting it. A header is only taken
+    /// if its bytes are the same before and after it is read, so it is not half written.
+    fn read_settled_header(&mut self) -> Result<metadata::Metadata<'metadata>> {
+        let mut previous_bytes = None;
+        let mut result = Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "header kept changing while it was read",
+        )
+        .into());
+
+        for _ in 0..HEADER_READ_ATTEMPTS {
+            self.storage.seek_to_start()?;
+            let metadata = match MSerializer::deserialize::<_, Item>(
+                &mut *self.storage,
+                self.metadata.signature,
+            ) {
+                Ok(metadata) => metadata,
+                Err(error) => {
+                    previous_bytes = None;
+                    result = Err(error.into());
+                    continue;
+                }
+            };
+
+            let mut bytes = vec![0; MSerializer::get_metadata_size_in_bytes(&metadata)];
+            self.storage.seek_to_start()?;
+            self.storage.read_exact(&mut bytes)?;
+            if previous_bytes.as_ref() == Some(&bytes) {
+                return Ok(metadata);
+            }
+            previous_bytes = Some(bytes);
+        }
+
+        result
+    }
+
+    fn read_page_generation(&mut self, page_index: usize) -> Result<u64> {
+        self.seek_to_page(page_index)?;
+
+        Ok(page::deserialize_stored_generation::<Item, PSerializer, _>(
+            &mut *self.storage,
+            self.metadata.count_elements_on_page::<Item>(),
+        )?)
+    }
+}
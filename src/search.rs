@@ -0,0 +1,104 @@
+use crate::{metadata, page, page::Bitmap, Result, Storage, VirtualArray};
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Returns the first index greater than or equal to `from` that holds a value.
+    pub fn next_present(&mut self, from: usize) -> Result<Option<usize>> {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let mut start_on_page = from % elements_count_on_page;
+
+        for page_index in from / elements_count_on_page..self.count_used_pages() {
+            let found =
+                self.with_bitmap(page_index, |bitmap| bitmap.next_set_from(start_on_page))?;
+            if let Some(index_on_page) = found {
+                return Ok(self.to_element_index(page_index, index_on_page));
+            }
+
+            start_on_page = 0;
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the last index less than or equal to `from` that holds a value.
+    pub fn prev_present(&mut self, from: usize) -> Result<Option<usize>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let from = from.min(self.len() - 1);
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let mut end_on_page = from % elements_count_on_page;
+
+        for page_index in (0..=from / elements_count_on_page).rev() {
+            let found = self.with_bitmap(page_index, |bitmap| bitmap.prev_set_from(end_on_page))?;
+            if let Some(index_on_page) = found {
+                return Ok(self.to_element_index(page_index, index_on_page));
+            }
+
+            end_on_page = elements_count_on_page - 1;
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the first index that holds no value.
+    pub fn first_free(&mut self) -> Result<Option<usize>> {
+        for page_index in 0..self.count_used_pages() {
+            let found = self.with_bitmap(page_index, |bitmap| bitmap.first_clear())?;
+            if let Some(index_on_page) = found {
+                return Ok(self.to_element_index(page_index, index_on_page));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Counts the elements that hold a value.
+    pub fn count_present(&mut self) -> Result<usize> {
+        let mut count = 0;
+        for page_index in 0..self.count_used_pages() {
+            count += self.with_bitmap(page_index, |bitmap| bitmap.count_ones())?;
+        }
+
+        Ok(count)
+    }
+
+    /// Gives access to the bitmap of a page. A buffered page is used as is, otherwise only the
+    /// bitmap is read from the storage and the page is not put into the buffer.
+    pub(crate) fn with_bitmap<F, R>(&mut self, page_index: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&Bitmap) -> R,
+    {
+        if let Some(page) = self.find_buffered_page(page_index) {
+            return Ok(f(&page.bitmap));
+        }
+
+        self.seek_to_page(page_index)?;
+        let bitmap = PSerializer::deserialize_bitmap(
+            &mut *self.storage,
+            self.metadata.count_elements_on_page::<Item>(),
+        )?;
+
+        Ok(f(&bitmap))
+    }
+
+    /// Number of pages that contain at least one index below the array length.
+    pub(crate) fn count_used_pages(&self) -> usize {
+        self.len()
+            .div_ceil(self.metadata.count_elements_on_page::<Item>())
+    }
+
+    fn to_element_index(&self, page_index: usize, index_on_page: usize) -> Option<usize> {
+        let element_index =
+            page_index * self.metadata.count_elements_on_page::<Item>() + index_on_page;
+
+        (element_index < self.len()).then_some(element_index)
+    }
+}
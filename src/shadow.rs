@@ -0,0 +1,389 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, ErrorKind, Read, SeekFrom},
+    ops::Range,
+    sync::Arc,
+};
+
+use crate::{checksum::Checksum, Storage};
+
+const ROOT_SIZE: u64 = 40;
+
+/// Page table of an array in shadow paging mode.
+///
+/// Pages live in slots of page size after two root records. A root record holds a generation,
+/// the number of slots, the location and length of the page table and a checksum over all of
+/// them and the table itself. Modified pages and the new table are written to free slots and
+/// the commit becomes visible when the root record older of the two is overwritten, so a
+/// failed commit leaves the previous root and everything it refers to intact. Slots that are
+/// no longer referenced become free once the commit that replaced them is complete, or once
+/// the last snapshot of an older generation is dropped.
+///
+/// The table is flat, so every commit writes all of it, 8 bytes per page, however few pages
+/// changed, and syncs the storage twice: after the pages and the table, and after the root.
+#[derive(Debug)]
+pub(crate) struct ShadowTable {
+    roots_start: u64,
+    page_size: u64,
+    generation: u64,
+    slots: Arc<Vec<u64>>,
+    table_slots: Range<u64>,
+    slot_count: u64,
+    free_slots: BTreeSet<u64>,
+    retained_slots: Vec<(u64, Vec<u64>)>,
+    staged_slots: BTreeMap<usize, u64>,
+    staged_len: usize,
+}
+
+struct Root {
+    generation: u64,
+    slot_count: u64,
+    table_start: u64,
+    table_len: u64,
+}
+
+impl ShadowTable {
+    /// Starts an empty table. Both root records are cleared, pages are added by staging them.
+    pub(crate) fn create<S: Storage>(
+        storage: &mut S,
+        roots_start: u64,
+        page_size: u64,
+    ) -> io::Result<Self> {
+        storage.seek(SeekFrom::Start(roots_start))?;
+        storage.write_all(&[0; 2 * ROOT_SIZE as usize])?;
+
+        Ok(Self {
+            roots_start,
+            page_size,
+            generation: 0,
+            slots: Arc::new(Vec::new()),
+            table_slots: 0..0,
+            slot_count: 0,
+            free_slots: BTreeSet::new(),
+            retained_slots: Vec::new(),
+            staged_slots: BTreeMap::new(),
+            staged_len: 0,
+        })
+    }
+
+    /// Loads the table of the newest intact root record.
+    pub(crate) fn open<S: Storage>(
+        storage: &mut S,
+        roots_start: u64,
+        page_size: u64,
+    ) -> io::Result<Self> {
+        let mut table = Self {
+            roots_start,
+            page_size,
+            generation: 0,
+            slots: Arc::new(Vec::new()),
+            table_slots: 0..0,
+            slot_count: 0,
+            free_slots: BTreeSet::new(),
+            retained_slots: Vec::new(),
+            staged_slots: BTreeMap::new(),
+            staged_len: 0,
+        };
+
+        let mut newest: Option<(Root, Vec<u64>)> = None;
+        for root_index in 0..2 {
+            if let Some((root, slots)) = table.read_root(storage, root_index)? {
+                if newest
+                    .as_ref()
+                    .is_none_or(|(newest, _)| root.generation > newest.generation)
+                {
+                    newest = Some((root, slots));
+                }
+            }
+        }
+
+        let Some((root, slots)) = newest else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "no intact page table root",
+            ));
+        };
+
+        let table_slots_count = table.count_table_slots(slots.len());
+        table.generation = root.generation;
+        table.slot_count = root.slot_count;
+        table.table_slots = root.table_start..root.table_start + table_slots_count;
+        table.staged_len = slots.len();
+        table.slots = Arc::new(slots);
+
+        let used_slots = table
+            .slots
+            .iter()
+            .copied()
+            .chain(table.table_slots.clone())
+            .collect::<BTreeSet<_>>();
+        table.free_slots = (0..table.slot_count)
+            .filter(|slot| !used_slots.contains(slot))
+            .collect();
+
+        Ok(table)
+    }
+
+    /// The committed page table, which stays readable until a snapshot holding it is dropped.
+    pub(crate) fn view(&self) -> PageTableView {
+        PageTableView {
+            slots: Arc::clone(&self.slots),
+            slots_start: self.slot_offset(0),
+            page_size: self.page_size,
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Offset of the page as of the last staged write.
+    pub(crate) fn page_offset(&self, page_index: usize) -> u64 {
+        let slot = match self.staged_slots.get(&page_index) {
+            Some(&slot) => slot,
+            None => self.slots[page_index],
+        };
+
+        self.slot_offset(slot)
+    }
+
+    /// Returns the offset a modified page should be written to. The first write of a page in a
+    /// commit moves it to a free slot, later writes reuse that slot.
+    pub(crate) fn stage_page(&mut self, page_index: usize) -> u64 {
+        let slot = match self.staged_slots.get(&page_index) {
+            Some(&slot) => slot,
+            None => {
+                let slot = self.allocate_slots(1).start;
+                self.staged_slots.insert(page_index, slot);
+                slot
+            }
+        };
+        self.staged_len = self.staged_len.max(page_index + 1);
+
+        self.slot_offset(slot)
+    }
+
+    /// Changes the number of pages in the next commit. New pages have to be staged.
+    pub(crate) fn stage_len(&mut self, len: usize) {
+        self.staged_len = len;
+        self.staged_slots.retain(|&page_index, _| page_index < len);
+    }
+
+    pub(crate) fn has_staged(&self) -> bool {
+        !self.staged_slots.is_empty() || self.staged_len != self.slots.len()
+    }
+
+    /// Writes the new page table and switches the root record. Storage must already contain
+    /// the staged pages. Slots of the replaced pages are kept while there is a snapshot of
+    /// `oldest_snapshot` generation or older. On failure the staged slots are not reused until
+    /// the array is opened again, as the new root may have reached the storage.
+    pub(crate) fn commit<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        oldest_snapshot: Option<u64>,
+    ) -> io::Result<()> {
+        let is_released =
+            |generation: u64| oldest_snapshot.is_none_or(|oldest| oldest > generation);
+        let (released, retained) = std::mem::take(&mut self.retained_slots)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(generation, _)| is_released(*generation));
+        self.retained_slots = retained;
+        self.free_slots
+            .extend(released.into_iter().flat_map(|(_, slots)| slots));
+
+        if !self.has_staged() {
+            return Ok(());
+        }
+
+        let result = self.write_commit(storage, is_released(self.generation));
+        self.staged_slots.clear();
+        self.staged_len = self.slots.len();
+
+        result
+    }
+
+    /// Forgets the staged pages when they could not be written.
+    pub(crate) fn discard_staged(&mut self) {
+        self.free_slots.extend(self.staged_slots.values());
+        self.staged_slots.clear();
+        self.staged_len = self.slots.len();
+    }
+
+    fn write_commit<S: Storage>(&mut self, storage: &mut S, is_released: bool) -> io::Result<()> {
+        let mut slots = self.slots.to_vec();
+        slots.truncate(self.staged_len);
+        for (&page_index, &slot) in self.staged_slots.range(slots.len()..) {
+            debug_assert_eq!(page_index, slots.len());
+            slots.push(slot);
+        }
+        for (&page_index, &slot) in self.staged_slots.range(..slots.len()) {
+            slots[page_index] = slot;
+        }
+
+        let table_bytes = slots
+            .iter()
+            .flat_map(|slot| slot.to_le_bytes())
+            .collect::<Vec<_>>();
+        let table_slots = self.allocate_slots(self.count_table_slots(slots.len()));
+        storage.seek(SeekFrom::Start(self.slot_offset(table_slots.start)))?;
+        storage.write_all(&table_bytes)?;
+        storage.sync()?;
+
+        let root = Root {
+            generation: self.generation + 1,
+            slot_count: self.slot_count,
+            table_start: table_slots.start,
+            table_len: slots.len() as u64,
+        };
+        storage.seek(SeekFrom::Start(self.root_offset(root.generation)))?;
+        storage.write_all(&encode_root(&root, &table_bytes))?;
+        storage.sync()?;
+
+        let used_slots = slots.iter().collect::<BTreeSet<_>>();
+        let replaced_slots = self
+            .slots
+            .iter()
+            .copied()
+            .filter(|slot| !used_slots.contains(slot))
+            .collect::<Vec<_>>();
+
+        if is_released {
+            self.free_slots.extend(replaced_slots);
+        } else {
+            self.retained_slots.push((self.generation, replaced_slots));
+        }
+        self.free_slots.extend(self.table_slots.clone());
+        self.slots = Arc::new(slots);
+        self.table_slots = table_slots;
+        self.generation = root.generation;
+
+        Ok(())
+    }
+
+    /// Takes the first run of `count` consecutive free slots, or appends new slots.
+    fn allocate_slots(&mut self, count: u64) -> Range<u64> {
+        let mut run = 0..0;
+
+        for &slot in &self.free_slots {
+            if run.end != slot || run.is_empty() {
+                run = slot..slot;
+            }
+            run.end = slot + 1;
+
+            if run.end - run.start == count {
+                for slot in run.clone() {
+                    self.free_slots.remove(&slot);
+                }
+                return run;
+            }
+        }
+
+        let run = self.slot_count..self.slot_count + count;
+        self.slot_count += count;
+        run
+    }
+
+    fn count_table_slots(&self, table_len: usize) -> u64 {
+        (table_len as u64 * 8).div_ceil(self.page_size).max(1)
+    }
+
+    fn slot_offset(&self, slot: u64) -> u64 {
+        self.roots_start + 2 * ROOT_SIZE + slot * self.page_size
+    }
+
+    fn root_offset(&self, generation: u64) -> u64 {
+        self.roots_start + (generation % 2) * ROOT_SIZE
+    }
+
+    fn read_root<S: Storage>(
+        &self,
+        storage: &mut S,
+        root_index: u64,
+    ) -> io::Result<Option<(Root, Vec<u64>)>> {
+        let mut bytes = [0u8; ROOT_SIZE as usize];
+        storage.seek(SeekFrom::Start(self.roots_start + root_index * ROOT_SIZE))?;
+        storage.read_exact(&mut bytes)?;
+
+        let field = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        let root = Root {
+            generation: field(0),
+            slot_count: field(1),
+            table_start: field(2),
+            table_len: field(3),
+        };
+        if root.generation == 0
+            || root.table_start >= root.slot_count
+            || root.table_len > root.slot_count
+        {
+            return Ok(None);
+        }
+
+        // A damaged root can hold any values, so the table is only read as far as the storage
+        // reaches.
+        let table_offset = root
+            .table_start
+            .checked_mul(self.page_size)
+            .and_then(|offset| offset.checked_add(self.slot_offset(0)));
+        let (Some(table_offset), Some(table_size)) = (table_offset, root.table_len.checked_mul(8))
+        else {
+            return Ok(None);
+        };
+
+        let mut table_bytes = Vec::new();
+        storage.seek(SeekFrom::Start(table_offset))?;
+        Read::by_ref(storage)
+            .take(table_size)
+            .read_to_end(&mut table_bytes)?;
+        if table_bytes.len() as u64 != table_size {
+            return Ok(None);
+        }
+
+        if encode_root(&root, &table_bytes) != bytes {
+            return Ok(None);
+        }
+
+        let slots = table_bytes
+            .chunks_exact(8)
+            .map(|slot| u64::from_le_bytes(slot.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if slots.iter().any(|&slot| slot >= root.slot_count) {
+            return Ok(None);
+        }
+
+        Ok(Some((root, slots)))
+    }
+}
+
+/// Page offsets of one committed generation.
+#[derive(Debug, Clone)]
+pub(crate) struct PageTableView {
+    slots: Arc<Vec<u64>>,
+    slots_start: u64,
+    page_size: u64,
+}
+
+impl PageTableView {
+    pub(crate) fn page_offset(&self, page_index: usize) -> u64 {
+        self.slots_start + self.slots[page_index] * self.page_size
+    }
+}
+
+fn encode_root(root: &Root, table_bytes: &[u8]) -> [u8; ROOT_SIZE as usize] {
+    let mut bytes = [0u8; ROOT_SIZE as usize];
+    let fields = [
+        root.generation,
+        root.slot_count,
+        root.table_start,
+        root.table_len,
+    ];
+    for (i, field) in fields.iter().enumerate() {
+        bytes[i * 8..i * 8 + 8].copy_from_slice(&field.to_le_bytes());
+    }
+
+    let mut checksum = Checksum::new(root.generation);
+    checksum.update(&bytes[..32]);
+    checksum.update(table_bytes);
+    bytes[32..].copy_from_slice(&checksum.finish().to_le_bytes());
+
+    bytes
+}
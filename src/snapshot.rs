@@ -0,0 +1,327 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    metadata,
+    page::{self, Page},
+    shadow::PageTableView,
+    PositionalStorage, Result, VirtualArray, VirtualArrayError,
+};
+
+/// Live snapshots of an array, shared between the array and its snapshots.
+///
+/// In place writes ask the registry before a page is overwritten, and its previous image is
+/// kept for every snapshot that does not hold an older image of that page already. In shadow
+/// paging mode pages are never overwritten and only the generation of each snapshot is used.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotRegistry {
+    next_id: u64,
+    snapshots: BTreeMap<u64, SnapshotEntry>,
+}
+
+#[derive(Debug)]
+struct SnapshotEntry {
+    generation: Option<u64>,
+    pages_count: usize,
+    preserved_pages: HashMap<usize, Arc<Vec<u8>>>,
+}
+
+impl SnapshotRegistry {
+    fn register(&mut self, generation: Option<u64>, pages_count: usize) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.snapshots.insert(
+            id,
+            SnapshotEntry {
+                generation,
+                pages_count,
+                preserved_pages: HashMap::new(),
+            },
+        );
+        id
+    }
+
+    fn unregister(&mut self, id: u64) {
+        self.snapshots.remove(&id);
+    }
+
+    pub(crate) fn oldest_generation(&self) -> Option<u64> {
+        self.snapshots
+            .values()
+            .filter_map(|snapshot| snapshot.generation)
+            .min()
+    }
+
+    /// Whether the current image of the page has to be preserved before it is overwritten.
+    pub(crate) fn should_preserve(&self, page_index: usize) -> bool {
+        self.snapshots.values().any(|snapshot| {
+            page_index < snapshot.pages_count && !snapshot.preserved_pages.contains_key(&page_index)
+        })
+    }
+
+    pub(crate) fn preserve(&mut self, page_index: usize, bytes: Vec<u8>) {
+        let bytes = Arc::new(bytes);
+
+        for snapshot in self.snapshots.values_mut() {
+            if page_index < snapshot.pages_count {
+                snapshot
+                    .preserved_pages
+                    .entry(page_index)
+                    .or_insert_with(|| Arc::clone(&bytes));
+            }
+        }
+    }
+}
+
+/// A read-only view of an array frozen at the time it was created.
+///
+/// The snapshot reads through its own handle of the storage and does not borrow the array,
+/// so the array can still be modified. Pages overwritten in place after the snapshot was taken
+/// are kept in memory until the last snapshot that needs them is dropped. In shadow paging mode
+/// the snapshot keeps the page table of its generation instead, and the slots it refers to are
+/// not reused until it is dropped.
+#[derive(Debug)]
+pub struct Snapshot<Item, Store, PSerializer> {
+    storage: Store,
+    registry: Arc<Mutex<SnapshotRegistry>>,
+    id: u64,
+    generation: u64,
+    header: Vec<u8>,
+    len: usize,
+    elements_count_on_page: usize,
+    has_page_generations: bool,
+    has_versioned_header: bool,
+    page_size: usize,
+    page_locations: PageLocations,
+    page: Option<Page<Item>>,
+    _marker: PhantomData<fn() -> PSerializer>,
+}
+
+#[derive(Debug)]
+enum PageLocations {
+    Fixed { pages_start: u64 },
+    Table(PageTableView),
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Saves pending changes and returns a snapshot of the current contents. Pages written
+    /// after the snapshot are stamped with a newer generation.
+    pub fn snapshot(&mut self) -> Result<Snapshot<Item, Store, PSerializer>> {
+        self.save()?;
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let pages_count = self.metadata.count_pages::<Item>();
+        let (shadow_generation, page_locations) = match &self.shadow {
+            Some(shadow) => (
+                Some(shadow.generation()),
+                PageLocations::Table(shadow.view()),
+            ),
+            None => (
+                None,
+                PageLocations::Fixed {
+                    pages_start: self.page_offset(0),
+                },
+            ),
+        };
+
+        // The header of an image in place layout, as written by a backup. It keeps the format
+        // version of the array, so that the pages are copied as they are.
+        let mut image_metadata = metadata::Metadata::new::<Item>(
+            self.metadata.signature,
+            self.metadata.data_chunk_size,
+            self.metadata.array_size,
+        )?;
+        image_metadata.format_major_version = self.metadata.format_major_version;
+        image_metadata.format_minor_version = self.metadata.format_minor_version;
+        image_metadata.length = self.metadata.length;
+        image_metadata.generation = self.metadata.generation;
+        image_metadata.compat_features = self.metadata.compat_features;
+        image_metadata.incompat_features = self.metadata.incompat_features;
+        image_metadata.unknown_fields = self.metadata.unknown_fields.clone();
+        image_metadata.item_layout = self.metadata.item_layout;
+        let mut header = Vec::new();
+        MSerializer::serialize(&mut header, &image_metadata)?;
+
+        let generation = self.metadata.generation;
+        self.advance_generation()?;
+
+        let storage = self.storage.try_clone()?;
+        let id = self
+            .snapshots
+            .lock()
+            .unwrap()
+            .register(shadow_generation, pages_count);
+
+        Ok(Snapshot {
+            storage,
+            registry: Arc::clone(&self.snapshots),
+            id,
+            generation,
+            header,
+            len: self.len(),
+            elements_count_on_page,
+            has_page_generations: self.metadata.has_page_generations(),
+            has_versioned_header: self.metadata.format_major_version
+                != metadata::UNVERSIONED_FORMAT_MAJOR_VERSION,
+            page_size: self.metadata.page_size::<Item, PSerializer>(),
+            page_locations,
+            page: None,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<Item, Store, PSerializer> Snapshot<Item, Store, PSerializer>
+where
+    Item: Default,
+    Store: PositionalStorage,
+    PSerializer: page::Serializer<Item>,
+{
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Generation of the array the snapshot was taken at. Pages changed after it have a newer
+    /// generation, so it is the one to pass to the next incremental backup.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get(&mut self, element_index: usize) -> Result<Option<&Item>> {
+        if element_index >= self.len {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index: element_index,
+                len: self.len,
+            });
+        }
+
+        let page_index = element_index / self.elements_count_on_page;
+        let page = match self.page.take() {
+            Some(page) if page.index == page_index => page,
+            _ => self.read_page(page_index)?,
+        };
+
+        Ok(self
+            .page
+            .insert(page)
+            .get(element_index % self.elements_count_on_page))
+    }
+
+    /// Calls `f` with the index and the value of every present element in order.
+    pub fn try_for_each_present<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &Item) -> Result<()>,
+    {
+        let pages_count = self.len.div_ceil(self.elements_count_on_page);
+
+        for page_index in 0..pages_count {
+            let page = self.read_page(page_index)?;
+            let page_start = page_index * self.elements_count_on_page;
+
+            for (index_on_page, value) in page.iter() {
+                f(page_start + index_on_page, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub(crate) fn count_pages(&self) -> usize {
+        self.len / self.elements_count_on_page + 1
+    }
+
+    /// Reads a page, clearing the flags of elements past the snapshot length.
+    fn read_page(&self, page_index: usize) -> Result<Page<Item>> {
+        let bytes = self.read_page_bytes(page_index)?;
+        let mut page = page::deserialize_stored::<_, PSerializer, _>(
+            &mut bytes.as_slice(),
+            page_index,
+            self.elements_count_on_page,
+            self.has_page_generations,
+        )?;
+
+        let used_on_page = self
+            .len
+            .saturating_sub(page_index * self.elements_count_on_page)
+            .min(self.elements_count_on_page);
+        if used_on_page < self.elements_count_on_page {
+            page.clear(used_on_page..self.elements_count_on_page);
+        }
+
+        Ok(page)
+    }
+
+    pub(crate) fn has_versioned_header(&self) -> bool {
+        self.has_versioned_header
+    }
+
+    /// Reads only the generation that follows a page, not the page itself.
+    pub(crate) fn read_page_generation(&self, page_index: usize) -> Result<u64> {
+        let generation_start = PSerializer::get_page_size_in_bytes(self.elements_count_on_page);
+        let mut bytes = [0; mem::size_of::<u64>()];
+        self.read_page_part(page_index, generation_start, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads a serialized page as of the time the snapshot was taken.
+    pub(crate) fn read_page_bytes(&self, page_index: usize) -> Result<Vec<u8>> {
+        let mut bytes = vec![0; self.page_size];
+        self.read_page_part(page_index, 0, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Fills `buffer` with the bytes of a serialized page from `start` on, as of the time the
+    /// snapshot was taken.
+    fn read_page_part(&self, page_index: usize, start: usize, buffer: &mut [u8]) -> Result<()> {
+        match &self.page_locations {
+            PageLocations::Table(view) => {
+                let offset = view.page_offset(page_index) + start as u64;
+                self.storage.read_exact_at(buffer, offset)?;
+            }
+            PageLocations::Fixed { pages_start } => {
+                // The registry stays locked while reading, so the writer cannot overwrite the
+                // page before it has preserved it.
+                let registry = self.registry.lock().unwrap();
+                let preserved = &registry.snapshots[&self.id].preserved_pages;
+
+                match preserved.get(&page_index) {
+                    Some(bytes) => buffer.copy_from_slice(&bytes[start..start + buffer.len()]),
+                    None => {
+                        let offset = pages_start + (page_index * self.page_size + start) as u64;
+                        self.storage.read_exact_at(buffer, offset)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<Item, Store, PSerializer> Drop for Snapshot<Item, Store, PSerializer> {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = self.registry.lock() {
+            registry.unregister(self.id);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    mem,
+    path::PathBuf,
+    sync::atomic::{self, AtomicUsize},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    metadata,
+    page::{self, DefaultSerializer, Page},
+    Result, Storage, VirtualArray,
+};
+
+/// Settings of the external merge sort used by [`VirtualArray::sort_by`] and friends.
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// Approximate number of bytes of items that are kept in memory at once.
+    pub memory_budget: usize,
+    /// Directory for the temporary file holding sorted runs.
+    pub temp_dir: PathBuf,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        Self {
+            memory_budget: 64 * 1024 * 1024,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    pub fn sort_options(&self) -> &SortOptions {
+        &self.sort_options
+    }
+
+    pub fn set_sort_options(&mut self, sort_options: SortOptions) {
+        self.sort_options = sort_options;
+    }
+
+    /// Stable sort of the present elements. After sorting the present elements occupy a dense
+    /// prefix of the array and all absent slots are at the end.
+    pub fn sort_by<F>(&mut self, compare: F) -> Result<()>
+    where
+        F: FnMut(&Item, &Item) -> Ordering,
+    {
+        self.external_sort(compare, true)
+    }
+
+    pub fn sort_unstable_by<F>(&mut self, compare: F) -> Result<()>
+    where
+        F: FnMut(&Item, &Item) -> Ordering,
+    {
+        self.external_sort(compare, false)
+    }
+
+    pub fn sort_by_key<K, F>(&mut self, mut f: F) -> Result<()>
+    where
+        K: Ord,
+        F: FnMut(&Item) -> K,
+    {
+        self.sort_by(|a, b| f(a).cmp(&f(b)))
+    }
+
+    /// Sorts runs of up to `memory_budget` bytes in memory and spills them to a temporary file,
+    /// then merges the runs straight into fresh pages of the array. Pages are read and written
+    /// past the buffer, so the buffer is emptied first.
+    fn external_sort<F>(&mut self, mut compare: F, is_stable: bool) -> Result<()>
+    where
+        F: FnMut(&Item, &Item) -> Ordering,
+    {
+        self.check_writable()?;
+        self.save()?;
+        self.pages.clear();
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let run_capacity =
+            (self.sort_options.memory_budget / mem::size_of::<Item>()).max(elements_count_on_page);
+
+        let mut runs_file: Option<RunsFile> = None;
+        let mut run = Vec::new();
+
+        for page_index in 0..self.count_used_pages() {
+            let mut page = self.read_page(page_index)?;
+            let page_start = page_index * elements_count_on_page;
+            let present_indices = page
+                .bitmap
+                .iter_ones()
+                .take_while(|index_on_page| page_start + index_on_page < self.len())
+                .collect::<Vec<_>>();
+
+            for index_on_page in present_indices {
+                run.push(page.take(index_on_page).unwrap());
+
+                if run.len() == run_capacity {
+                    sort_run(&mut run, &mut compare, is_stable);
+                    let runs_file = match &mut runs_file {
+                        Some(runs_file) => runs_file,
+                        None => runs_file.insert(RunsFile::create(&self.sort_options)?),
+                    };
+                    runs_file.write_run(&mut run)?;
+                }
+            }
+        }
+
+        sort_run(&mut run, &mut compare, is_stable);
+
+        match runs_file {
+            None => {
+                let mut items = run.into_iter();
+                self.write_sorted(|| Ok(items.next()))
+            }
+            Some(mut runs_file) => {
+                runs_file.write_run(&mut run)?;
+
+                let mut merger = Merger::new(runs_file, run_capacity)?;
+                self.write_sorted(|| merger.next(&mut compare))
+            }
+        }
+    }
+
+    fn write_sorted<F>(&mut self, mut next_item: F) -> Result<()>
+    where
+        F: FnMut() -> Result<Option<Item>>,
+    {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let mut is_exhausted = false;
+
+        for page_index in 0..self.count_used_pages() {
+            let mut page = Page::empty(page_index, elements_count_on_page);
+
+            for index_on_page in 0..elements_count_on_page {
+                if is_exhausted {
+                    break;
+                }
+
+                match next_item()? {
+                    Some(item) => page.set(index_on_page, item),
+                    None => is_exhausted = true,
+                }
+            }
+
+            self.write_page(&mut page)?;
+        }
+
+        self.storage.flush()?;
+        Ok(())
+    }
+}
+
+fn sort_run<Item, F>(run: &mut [Item], compare: &mut F, is_stable: bool)
+where
+    F: FnMut(&Item, &Item) -> Ordering,
+{
+    if is_stable {
+        run.sort_by(compare);
+    } else {
+        run.sort_unstable_by(compare);
+    }
+}
+
+/// Temporary file with sorted runs, removed on drop.
+struct RunsFile {
+    file: File,
+    path: PathBuf,
+    runs: Vec<Run>,
+    end: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Run {
+    offset: u64,
+    len: usize,
+}
+
+impl RunsFile {
+    fn create(sort_options: &SortOptions) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+        let path = sort_options.temp_dir.join(format!(
+            "virtual_array_sort_{}_{}_{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, atomic::Ordering::Relaxed),
+            nanos
+        ));
+
+        let file = OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+
+        Ok(Self {
+            file,
+            path,
+            runs: Vec::new(),
+            end: 0,
+        })
+    }
+
+    fn write_run<Item>(&mut self, run: &mut Vec<Item>) -> Result<()> {
+        if run.is_empty() {
+            return Ok(());
+        }
+
+        let bytes = DefaultSerializer::convert_items_to_bytes(run);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(bytes)?;
+
+        self.runs.push(Run {
+            offset: self.end,
+            len: run.len(),
+        });
+        self.end += bytes.len() as u64;
+        run.clear();
+
+        Ok(())
+    }
+
+    fn read_items<Item>(&mut self, offset: u64, count: usize) -> Result<Vec<Item>> {
+        let mut buffer = vec![0; count * mem::size_of::<Item>()];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buffer)?;
+
+        Ok(DefaultSerializer::convert_bytes_to_items(buffer, count))
+    }
+}
+
+impl Drop for RunsFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// K-way merge of the runs. Each run is read through its own buffer, together the buffers
+/// hold about one run worth of items. Ties are resolved in favour of the earlier run, which
+/// keeps the merge stable.
+struct Merger<Item> {
+    runs_file: RunsFile,
+    readers: Vec<RunReader<Item>>,
+    buffer_capacity: usize,
+}
+
+struct RunReader<Item> {
+    run: Run,
+    consumed: usize,
+    buffer: Vec<Item>,
+    head: Option<Item>,
+}
+
+impl<Item> Merger<Item> {
+    fn new(mut runs_file: RunsFile, items_in_memory: usize) -> Result<Self> {
+        let buffer_capacity = (items_in_memory / runs_file.runs.len()).max(1);
+        let mut readers = runs_file
+            .runs
+            .iter()
+            .map(|&run| RunReader {
+                run,
+                consumed: 0,
+                buffer: Vec::new(),
+                head: None,
+            })
+            .collect::<Vec<_>>();
+
+        for reader in readers.iter_mut() {
+            reader.advance(&mut runs_file, buffer_capacity)?;
+        }
+
+        Ok(Self {
+            runs_file,
+            readers,
+            buffer_capacity,
+        })
+    }
+
+    fn next<F>(&mut self, compare: &mut F) -> Result<Option<Item>>
+    where
+        F: FnMut(&Item, &Item) -> Ordering,
+    {
+        let mut min_pos: Option<usize> = None;
+
+        for (pos, reader) in self.readers.iter().enumerate() {
+            let Some(head) = &reader.head else {
+                continue;
+            };
+
+            let is_less = match min_pos {
+                None => true,
+                Some(min_pos) => {
+                    compare(head, self.readers[min_pos].head.as_ref().unwrap()) == Ordering::Less
+                }
+            };
+            if is_less {
+                min_pos = Some(pos);
+            }
+        }
+
+        let Some(min_pos) = min_pos else {
+            return Ok(None);
+        };
+
+        let reader = &mut self.readers[min_pos];
+        let item = reader.head.take();
+        reader.advance(&mut self.runs_file, self.buffer_capacity)?;
+
+        Ok(item)
+    }
+}
+
+impl<Item> RunReader<Item> {
+    fn advance(&mut self, runs_file: &mut RunsFile, buffer_capacity: usize) -> Result<()> {
+        if self.buffer.is_empty() && self.consumed < self.run.len {
+            let count = buffer_capacity.min(self.run.len - self.consumed);
+            let offset = self.run.offset + (self.consumed * mem::size_of::<Item>()) as u64;
+
+            self.buffer = runs_file.read_items(offset, count)?;
+            self.buffer.reverse();
+            self.consumed += count;
+        }
+
+        self.head = self.buffer.pop();
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    entry::{ElementGuard, Entry},
+    metadata, page, Result, Storage, VirtualArray,
+};
+
+/// A group of changes that reach the storage together.
+///
+/// Changes are made to the buffered pages, which stay pinned in the buffer until the
+/// transaction ends, and are written as one batch on [`commit`](Transaction::commit). A
+/// transaction that is dropped without being committed is rolled back.
+///
+/// The batch is only written all or nothing with a write-ahead log or in shadow paging mode.
+/// Otherwise pages are overwritten in place one after another, and a commit that fails or is
+/// interrupted can leave some of them written.
+#[derive(Debug)]
+pub struct Transaction<'array, 'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    array: &'array mut VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>,
+    is_finished: bool,
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// Saves pending changes and starts a transaction.
+    pub fn begin(
+        &mut self,
+    ) -> Result<Transaction<'_, 'metadata, Item, Store, PSerializer, MSerializer>> {
+        self.save()?;
+        self.in_transaction = true;
+
+        Ok(Transaction {
+            array: self,
+            is_finished: false,
+        })
+    }
+
+    /// Runs `f` in a transaction, which is committed if `f` succeeds and rolled back if it
+    /// fails.
+    pub fn transaction<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(
+            &mut Transaction<'_, 'metadata, Item, Store, PSerializer, MSerializer>,
+        ) -> Result<R>,
+    {
+        let mut transaction = self.begin()?;
+
+        match f(&mut transaction) {
+            Ok(result) => {
+                transaction.commit()?;
+                Ok(result)
+            }
+            Err(error) => {
+                transaction.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Leaves the transaction. Modified pages are written if `should_commit` is set. Otherwise,
+    /// or if writing them fails, they are read again so the buffer matches the storage, which
+    /// holds part of the batch if it failed while writing in place without a log.
+    fn end_transaction(&mut self, should_commit: bool) -> Result<()> {
+        self.in_transaction = false;
+
+        let mut result = if should_commit { self.save() } else { Ok(()) };
+
+        if !should_commit || result.is_err() {
+            for pos in 0..self.pages.len() {
+                if self.pages[pos].should_be_saved() {
+                    match self.read_page(self.pages[pos].index) {
+                        Ok(page) => self.pages[pos] = page,
+                        Err(error) => {
+                            result = result.and(Err(error));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        while result.is_ok() && self.pages.len() > self.buffer_size {
+            let max_priority_pos = self.get_buff_max_priority_pos().unwrap();
+            let mut evicted_page = self.pages.swap_remove(max_priority_pos);
+
+            if evicted_page.should_be_saved() {
+                result = self.write_page(&mut evicted_page);
+            }
+        }
+
+        if should_commit && result.is_ok() {
+            result = self.end_changed_generation();
+        }
+
+        result
+    }
+}
+
+impl<Item, Store, PSerializer, MSerializer>
+    Transaction<'_, '_, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    pub fn set(&mut self, element_index: usize, value: Item) -> Result<()> {
+        self.array.set(element_index, value)
+    }
+
+    pub fn get(&mut self, element_index: usize) -> Result<Option<&Item>> {
+        self.array.get(element_index)
+    }
+
+    pub fn get_mut(&mut self, element_index: usize) -> Result<Option<ElementGuard<'_, Item>>> {
+        self.array.get_mut(element_index)
+    }
+
+    pub fn update<F, R>(&mut self, element_index: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(Entry<'_, Item>) -> R,
+    {
+        self.array.update(element_index, f)
+    }
+
+    pub fn entry(&mut self, element_index: usize) -> Result<Entry<'_, Item>> {
+        self.array.entry(element_index)
+    }
+
+    pub fn delete(&mut self, element_index: usize) -> Result<()> {
+        self.array.delete(element_index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty()
+    }
+
+    /// Writes all changes of the transaction. If writing fails, the buffered changes are
+    /// discarded and the pages are read again. With a write-ahead log or shadow paging none of
+    /// the changes are then visible, otherwise the pages written before the failure keep them.
+    pub fn commit(mut self) -> Result<()> {
+        self.is_finished = true;
+        self.array.end_transaction(true)
+    }
+
+    /// Discards all changes of the transaction.
+    pub fn rollback(mut self) -> Result<()> {
+        self.is_finished = true;
+        self.array.end_transaction(false)
+    }
+}
+
+impl<Item, Store, PSerializer, MSerializer> Drop
+    for Transaction<'_, '_, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    fn drop(&mut self) {
+        if !self.is_finished {
+            if let Err(error) = self.array.end_transaction(false) {
+                (self.array.drop_error_hook)(&error);
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::mem::ManuallyDrop;
+
+use crate::{metadata, page, Result, Storage, VirtualArray, VirtualArrayError};
+
+/// A growable array with a logical length, stored on top of a [`VirtualArray`].
+///
+/// The capacity of the underlying array grows geometrically, while the length is kept in
+/// the metadata header and restored when the array is opened again. Converting an array
+/// that has never been used as a vector gives an empty vector with the array's capacity.
+#[derive(Debug)]
+pub struct VirtualVec<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    array: ManuallyDrop<VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>>,
+    is_length_saved: bool,
+    is_closed: bool,
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    From<VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>>
+    for VirtualVec<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    fn from(array: VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>) -> Self {
+        Self {
+            array: ManuallyDrop::new(array),
+            is_length_saved: true,
+            is_closed: false,
+        }
+    }
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualVec<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    pub fn len(&self) -> usize {
+        self.array.metadata.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let required_capacity = self.len() + additional;
+        if required_capacity <= self.capacity() {
+            return Ok(());
+        }
+
+        let new_capacity = required_capacity.max(self.capacity() * 2);
+        self.array.resize(new_capacity)?;
+        self.is_length_saved = true;
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: Item) -> Result<()> {
+        self.reserve(1)?;
+
+        let len = self.len();
+        self.array.set(len, value)?;
+        self.set_len(len + 1);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<Option<Item>> {
+        let Some(last_index) = self.len().checked_sub(1) else {
+            return Ok(None);
+        };
+
+        let value = self.array.take(last_index)?;
+        self.array.save()?;
+        self.set_len(last_index);
+        Ok(value)
+    }
+
+    pub fn last(&mut self) -> Result<Option<&Item>> {
+        match self.len().checked_sub(1) {
+            Some(last_index) => self.array.get(last_index),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get(&mut self, index: usize) -> Result<Option<&Item>> {
+        self.check_index(index)?;
+        self.array.get(index)
+    }
+
+    pub fn set(&mut self, index: usize, value: Item) -> Result<()> {
+        self.check_index(index)?;
+        self.array.set(index, value)
+    }
+
+    pub fn append<I>(&mut self, items: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Item>,
+    {
+        let items = items.into_iter();
+        self.reserve(items.size_hint().0)?;
+
+        for item in items {
+            self.push(item)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        if len >= self.len() {
+            return Ok(());
+        }
+
+        let old_len = self.len();
+        self.array.clear(len..old_len)?;
+        self.set_len(len);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.truncate(0)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.save_length()?;
+        self.array.flush()
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.array.metadata.length = len;
+        self.is_length_saved = false;
+    }
+
+    /// Saves the length and closes the underlying array, see [`VirtualArray::close`].
+    pub fn close(mut self) -> Result<Store> {
+        self.save_length()?;
+
+        self.is_closed = true;
+        // SAFETY: the vector is closed, so the array is neither used nor dropped again.
+        let array = unsafe { ManuallyDrop::take(&mut self.array) };
+        array.close()
+    }
+
+    fn save_length(&mut self) -> Result<()> {
+        if !self.is_length_saved {
+            self.array.write_metadata()?;
+            self.is_length_saved = true;
+        }
+
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.len() {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index,
+                len: self.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer> Drop
+    for VirtualVec<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    fn drop(&mut self) {
+        if self.is_closed {
+            return;
+        }
+
+        if let Err(error) = self.save_length() {
+            (self.array.drop_error_hook)(&error);
+        }
+
+        // SAFETY: the array is only taken out by `close`, which marks the vector as closed.
+        unsafe { ManuallyDrop::drop(&mut self.array) };
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{self, ErrorKind, SeekFrom};
+
+use crate::{metadata, page, Result, Storage, VirtualArray};
+
+/// How the array was left by the run that last wrote it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownStatus {
+    /// The array was closed, or was never modified after it was opened.
+    Clean,
+    /// The array was modified and not closed, so pages may be missing or partly written.
+    Unclean,
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// How the previous run left the array when it was opened. After an unclean shutdown the
+    /// data can be checked with [`verify`](Self::verify) before it is trusted.
+    pub fn shutdown_status(&self) -> ShutdownStatus {
+        self.shutdown_status
+    }
+
+    /// Checks the geometry of the stored array: the length fits the array size, and every page
+    /// lies within the storage, can be read and is not stamped with a generation later than
+    /// the header's. Fails with [`io::ErrorKind::InvalidData`] naming the first damaged part.
+    pub fn verify(&mut self) -> Result<()> {
+        let invalid_data = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+
+        self.save()?;
+
+        if self.metadata.length > self.metadata.array_size {
+            return Err(invalid_data(format!(
+                "length {} exceeds the array size {}",
+                self.metadata.length, self.metadata.array_size
+            ))
+            .into());
+        }
+
+        let storage_size = self.storage.seek(SeekFrom::End(0))?;
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let page_size = self.metadata.page_size::<Item, PSerializer>() as u64;
+
+        for page_index in 0..self.metadata.count_pages::<Item>() {
+            if self.page_offset(page_index) + page_size > storage_size {
+                return Err(invalid_data(format!("page {page_index} is truncated")).into());
+            }
+
+            self.seek_to_page(page_index)?;
+            let page = page::deserialize_stored::<_, PSerializer, _>(
+                &mut *self.storage,
+                page_index,
+                elements_count_on_page,
+                self.metadata.has_page_generations(),
+            )
+            .map_err(|_| invalid_data(format!("page {page_index} cannot be read")))?;
+
+            if page.generation() > self.metadata.generation {
+                return Err(
+                    invalid_data(format!("page {page_index} is newer than the header")).into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind, Read, SeekFrom, Write},
+};
+
+use crate::{
+    checksum::Checksum,
+    metadata,
+    page::{self, Page},
+    Result, Storage, VirtualArray, VirtualArrayError,
+};
+
+const MAGIC: &[u8; 8] = b"VAVER\0\0\x01";
+const HEADER_SIZE: u64 = MAGIC.len() as u64;
+
+const PAGE_RECORD: u8 = 1;
+const VERSION_RECORD: u8 = 2;
+
+const RECORD_HEAD_SIZE: usize = 25;
+const VERSION_RECORD_SIZE: u64 = RECORD_HEAD_SIZE as u64 + 16;
+
+/// Log of previous page images of a versioned array.
+///
+/// Before a page is overwritten for the first time in a generation, its image is appended
+/// together with the last generation it belongs to. A version record with the size and the
+/// length of the array is appended when a generation ends. A record is a kind byte, the
+/// generation, a page index or the array size, a payload length, the payload and a checksum,
+/// and a damaged tail of the log is ignored. Records older than the retention are dropped, and
+/// the log is rewritten once most of it is dropped.
+#[derive(Debug)]
+pub(crate) struct VersionLog {
+    storage: Box<dyn Storage + Send>,
+    retention: u64,
+    /// Offset and size of the page records by page index and generation.
+    page_records: BTreeMap<(usize, u64), (u64, u64)>,
+    versions: BTreeMap<u64, Version>,
+    end: u64,
+}
+
+/// Kind, generation, page index or array size, and payload of a record.
+type Record = (u8, u64, u64, Vec<u8>);
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Version {
+    pub(crate) array_size: usize,
+    pub(crate) length: usize,
+}
+
+impl VersionLog {
+    /// Starts an empty log, dropping whatever the storage held.
+    pub(crate) fn create(storage: Box<dyn Storage + Send>, retention: u64) -> io::Result<Self> {
+        let mut log = Self {
+            storage,
+            retention,
+            page_records: BTreeMap::new(),
+            versions: BTreeMap::new(),
+            end: HEADER_SIZE,
+        };
+
+        log.storage.seek_to_start()?;
+        log.storage.write_all(MAGIC)?;
+        match log.storage.set_len(HEADER_SIZE) {
+            Err(error) if error.kind() != ErrorKind::Unsupported => return Err(error),
+            _ => {}
+        }
+        log.storage.sync()?;
+
+        Ok(log)
+    }
+
+    /// Opens the log, starting a new one if the storage is empty.
+    pub(crate) fn open(storage: Box<dyn Storage + Send>, retention: u64) -> io::Result<Self> {
+        let mut log = Self {
+            storage,
+            retention,
+            page_records: BTreeMap::new(),
+            versions: BTreeMap::new(),
+            end: HEADER_SIZE,
+        };
+
+        log.storage.seek_to_start()?;
+        let mut magic = [0u8; MAGIC.len()];
+        match log.storage.read_exact(&mut magic) {
+            Ok(()) if &magic == MAGIC => {}
+            Ok(()) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid version log header",
+                ))
+            }
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => {
+                return Self::create(log.storage, retention);
+            }
+            Err(error) => return Err(error),
+        }
+
+        while let Some((kind, generation, value, payload)) = log.read_record()? {
+            let start = log.end;
+            let end = log.storage.stream_position()?;
+
+            match kind {
+                PAGE_RECORD => {
+                    log.page_records
+                        .insert((value as usize, generation), (start, end - start));
+                }
+                VERSION_RECORD if payload.len() == 8 => {
+                    let version = Version {
+                        array_size: value as usize,
+                        length: u64::from_le_bytes(payload.try_into().unwrap()) as usize,
+                    };
+                    log.versions.insert(generation, version);
+                }
+                _ => break,
+            }
+            log.end = end;
+        }
+
+        Ok(log)
+    }
+
+    /// The oldest generation that can still be read while `current` is being written.
+    pub(crate) fn oldest_generation(&self, current: u64) -> u64 {
+        let oldest_kept = self.versions.keys().next().copied().unwrap_or(current);
+        current.saturating_sub(self.retention).max(oldest_kept)
+    }
+
+    /// Appends the previous images of pages, each with the last generation it belongs to, and
+    /// syncs the log so they are kept before the pages are overwritten.
+    pub(crate) fn append_pages<B: AsRef<[u8]>>(
+        &mut self,
+        pages: &[(usize, u64, B)],
+    ) -> io::Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let mut buffer = Vec::new();
+        let mut records = Vec::new();
+        for (page_index, generation, bytes) in pages {
+            let start = buffer.len() as u64;
+            encode_record(
+                &mut buffer,
+                PAGE_RECORD,
+                *generation,
+                *page_index as u64,
+                bytes.as_ref(),
+            );
+            let record = (self.end + start, buffer.len() as u64 - start);
+            records.push(((*page_index, *generation), record));
+        }
+
+        self.append(&buffer)?;
+        self.page_records.extend(records);
+        Ok(())
+    }
+
+    /// Records the size of the array at the end of `generation` and drops what is no longer
+    /// retained.
+    pub(crate) fn end_version(&mut self, generation: u64, version: Version) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        encode_record(
+            &mut buffer,
+            VERSION_RECORD,
+            generation,
+            version.array_size as u64,
+            &(version.length as u64).to_le_bytes(),
+        );
+        self.append(&buffer)?;
+        self.versions.insert(generation, version);
+
+        let oldest = (generation + 1).saturating_sub(self.retention);
+        self.versions.retain(|&generation, _| generation >= oldest);
+        self.page_records
+            .retain(|&(_, generation), _| generation >= oldest);
+
+        let live_bytes = self.versions.len() as u64 * VERSION_RECORD_SIZE
+            + self
+                .page_records
+                .values()
+                .map(|(_, size)| size)
+                .sum::<u64>();
+        if self.end - HEADER_SIZE > 2 * live_bytes {
+            self.rewrite()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn version(&self, generation: u64) -> Option<Version> {
+        self.versions
+            .range(generation..)
+            .next()
+            .map(|(_, &version)| version)
+    }
+
+    /// Reads the image of a page as of the end of `generation`, or `None` if the page has not
+    /// been overwritten since.
+    pub(crate) fn read_page(
+        &mut self,
+        page_index: usize,
+        generation: u64,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let Some(&(offset, _)) = self
+            .page_records
+            .range((page_index, generation)..=(page_index, u64::MAX))
+            .next()
+            .map(|(_, record)| record)
+        else {
+            return Ok(None);
+        };
+
+        self.storage.seek(SeekFrom::Start(offset))?;
+        match self.read_record()? {
+            Some((PAGE_RECORD, _, _, payload)) => Ok(Some(payload)),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "damaged version log record",
+            )),
+        }
+    }
+
+    fn append(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.storage.seek(SeekFrom::Start(self.end))?;
+        self.storage.write_all(buffer)?;
+        self.storage.sync()?;
+        self.end += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Copies the retained records to the start of the log. Records only move towards the
+    /// start, so a record is read before anything is written over it.
+    fn rewrite(&mut self) -> io::Result<()> {
+        let mut records = self
+            .page_records
+            .iter()
+            .map(|(&key, &record)| (record, key))
+            .collect::<Vec<_>>();
+        records.sort_unstable();
+
+        let mut end = HEADER_SIZE;
+        let mut page_records = BTreeMap::new();
+        for ((offset, size), key) in records {
+            let mut buffer = vec![0; size as usize];
+            self.storage.seek(SeekFrom::Start(offset))?;
+            self.storage.read_exact(&mut buffer)?;
+
+            self.storage.seek(SeekFrom::Start(end))?;
+            self.storage.write_all(&buffer)?;
+            page_records.insert(key, (end, size));
+            end += size;
+        }
+
+        for (&generation, version) in &self.versions {
+            let mut buffer = Vec::new();
+            encode_record(
+                &mut buffer,
+                VERSION_RECORD,
+                generation,
+                version.array_size as u64,
+                &(version.length as u64).to_le_bytes(),
+            );
+            self.storage.seek(SeekFrom::Start(end))?;
+            self.storage.write_all(&buffer)?;
+            end += buffer.len() as u64;
+        }
+
+        match self.storage.set_len(end) {
+            Err(error) if error.kind() != ErrorKind::Unsupported => return Err(error),
+            _ => {}
+        }
+        self.storage.sync()?;
+
+        self.page_records = page_records;
+        self.end = end;
+        Ok(())
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut head = [0u8; RECORD_HEAD_SIZE];
+        if !self.read_or_eof(&mut head)? {
+            return Ok(None);
+        }
+
+        let kind = head[0];
+        let generation = u64::from_le_bytes(head[1..9].try_into().unwrap());
+        let value = u64::from_le_bytes(head[9..17].try_into().unwrap());
+        let payload_len = u64::from_le_bytes(head[17..25].try_into().unwrap());
+
+        let mut payload = Vec::new();
+        let read_len = (&mut self.storage)
+            .take(payload_len)
+            .read_to_end(&mut payload)?;
+        let mut stored_checksum = [0u8; 8];
+        if read_len as u64 != payload_len || !self.read_or_eof(&mut stored_checksum)? {
+            return Ok(None);
+        }
+
+        let mut checksum = Checksum::new(0);
+        checksum.update(&head);
+        checksum.update(&payload);
+        if checksum.finish() != u64::from_le_bytes(stored_checksum) {
+            return Ok(None);
+        }
+
+        Ok(Some((kind, generation, value, payload)))
+    }
+
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> io::Result<bool> {
+        match self.storage.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<'metadata, Item, Store, PSerializer, MSerializer>
+    VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    /// The generation that is being written. It ends with every snapshot, and with every flush,
+    /// checkpoint and committed transaction that wrote something.
+    pub fn generation(&self) -> u64 {
+        self.metadata.generation
+    }
+
+    /// The oldest generation that can be read with [`get_at`](Self::get_at), which is the
+    /// current one unless the array is versioned.
+    pub fn oldest_generation(&self) -> u64 {
+        match &self.versions {
+            Some(versions) => versions.oldest_generation(self.metadata.generation),
+            None => self.metadata.generation,
+        }
+    }
+
+    /// Returns the value of an element as of the end of `generation`.
+    pub fn get_at(&mut self, element_index: usize, generation: u64) -> Result<Option<Item>> {
+        let version = self.version_at(generation)?;
+        if element_index >= version.array_size {
+            return Err(VirtualArrayError::IndexOutOfBounds {
+                index: element_index,
+                len: version.array_size,
+            });
+        }
+
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let mut page = self.read_page_at(
+            element_index / elements_count_on_page,
+            generation,
+            version.array_size,
+        )?;
+
+        Ok(page.take(element_index % elements_count_on_page))
+    }
+
+    /// Returns an iterator over the index and the value of every element that was present at
+    /// the end of `generation`.
+    pub fn iter_at(
+        &mut self,
+        generation: u64,
+    ) -> Result<VersionIter<'_, 'metadata, Item, Store, PSerializer, MSerializer>> {
+        let version = self.version_at(generation)?;
+
+        Ok(VersionIter {
+            array: self,
+            generation,
+            array_size: version.array_size,
+            page: None,
+            next_index: 0,
+        })
+    }
+
+    /// Reverts the contents of the array to the end of `generation`. The reverted pages are
+    /// written in the current generation, so the rollback can be rolled back too.
+    pub fn rollback_to(&mut self, generation: u64) -> Result<()> {
+        self.check_writable()?;
+        let version = self.version_at(generation)?;
+        if generation == self.metadata.generation {
+            return Ok(());
+        }
+
+        self.pages.clear();
+        self.resize(version.array_size)?;
+
+        for page_index in 0..self.metadata.count_pages::<Item>() {
+            let Some(versions) = &mut self.versions else {
+                break;
+            };
+            let Some(bytes) = versions.read_page(page_index, generation)? else {
+                continue;
+            };
+
+            let mut page = page::deserialize_stored::<_, PSerializer, _>(
+                &mut bytes.as_slice(),
+                page_index,
+                self.metadata.count_elements_on_page::<Item>(),
+                self.metadata.has_page_generations(),
+            )?;
+            self.write_page(&mut page)?;
+        }
+
+        self.metadata.length = version.length;
+        self.write_metadata()
+    }
+
+    /// Saves pending changes and returns the size of the array at the end of `generation`.
+    fn version_at(&mut self, generation: u64) -> Result<Version> {
+        self.save()?;
+
+        let unavailable = VirtualArrayError::GenerationUnavailable {
+            generation,
+            oldest: self.oldest_generation(),
+            current: self.metadata.generation,
+        };
+        if generation == self.metadata.generation {
+            return Ok(Version {
+                array_size: self.metadata.array_size,
+                length: self.metadata.length,
+            });
+        }
+        if generation < self.oldest_generation() || generation > self.metadata.generation {
+            return Err(unavailable);
+        }
+
+        self.versions
+            .as_ref()
+            .and_then(|versions| versions.version(generation))
+            .ok_or(unavailable)
+    }
+
+    /// Reads a page as of the end of `generation`, clearing the flags of elements past the
+    /// size the array had then.
+    fn read_page_at(
+        &mut self,
+        page_index: usize,
+        generation: u64,
+        array_size: usize,
+    ) -> Result<Page<Item>> {
+        let elements_count_on_page = self.metadata.count_elements_on_page::<Item>();
+        let image = match &mut self.versions {
+            Some(versions) if generation < self.metadata.generation => {
+                versions.read_page(page_index, generation)?
+            }
+            _ => None,
+        };
+
+        let mut page = match image {
+            Some(bytes) => page::deserialize_stored::<_, PSerializer, _>(
+                &mut bytes.as_slice(),
+                page_index,
+                elements_count_on_page,
+                self.metadata.has_page_generations(),
+            )?,
+            None => self.read_page(page_index)?,
+        };
+
+        let used_on_page = array_size
+            .saturating_sub(page_index * elements_count_on_page)
+            .min(elements_count_on_page);
+        if used_on_page < elements_count_on_page {
+            page.clear(used_on_page..elements_count_on_page);
+        }
+
+        Ok(page)
+    }
+}
+
+/// Iterator over the present elements of an array as of the end of a past generation,
+/// returned by [`VirtualArray::iter_at`].
+#[derive(Debug)]
+pub struct VersionIter<'array, 'metadata, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    array: &'array mut VirtualArray<'metadata, Item, Store, PSerializer, MSerializer>,
+    generation: u64,
+    array_size: usize,
+    page: Option<Page<Item>>,
+    next_index: usize,
+}
+
+impl<Item, Store, PSerializer, MSerializer> Iterator
+    for VersionIter<'_, '_, Item, Store, PSerializer, MSerializer>
+where
+    Item: Default,
+    Store: Storage,
+    PSerializer: page::Serializer<Item>,
+    MSerializer: metadata::Serializer,
+{
+    type Item = Result<(usize, Item)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let elements_count_on_page = self.array.metadata.count_elements_on_page::<Item>();
+
+        while self.next_index < self.array_size {
+            let element_index = self.next_index;
+            let page_index = element_index / elements_count_on_page;
+            self.next_index += 1;
+
+            let page = match &mut self.page {
+                Some(page) if page.index == page_index => page,
+                page => {
+                    let read_page =
+                        self.array
+                            .read_page_at(page_index, self.generation, self.array_size);
+                    match read_page {
+                        Ok(read_page) => page.insert(read_page),
+                        Err(error) => {
+                            self.next_index = self.array_size;
+                            return Some(Err(error));
+                        }
+                    }
+                }
+            };
+
+            if let Some(value) = page.take(element_index % elements_count_on_page) {
+                return Some(Ok((element_index, value)));
+            }
+        }
+
+        None
+    }
+}
+
+fn encode_record(buffer: &mut Vec<u8>, kind: u8, generation: u64, value: u64, payload: &[u8]) {
+    let start = buffer.len();
+
+    buffer.push(kind);
+    buffer.extend_from_slice(&generation.to_le_bytes());
+    buffer.extend_from_slice(&value.to_le_bytes());
+    buffer.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(payload);
+
+    let mut checksum = Checksum::new(0);
+    checksum.update(&buffer[start..]);
+    buffer.extend_from_slice(&checksum.finish().to_le_bytes());
+}
@@ -0,0 +1,174 @@
+use std::io::{self, ErrorKind, Read, SeekFrom, Write};
+
+use crate::{checksum::Checksum, Storage};
+
+const MAGIC: &[u8; 8] = b"VAWAL\0\0\x01";
+const HEADER_SIZE: u64 = 16;
+
+const PAGE_RECORD: u8 = 1;
+const COMMIT_RECORD: u8 = 2;
+
+const DEFAULT_CHECKPOINT_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Write-ahead log of serialized pages.
+///
+/// The log starts with a header of the magic bytes and a salt, followed by records. A record
+/// is a kind byte, a page index (or the count of pages for a commit record), a payload length,
+/// the payload and a checksum seeded with the salt. Pages of a batch are only applied when the
+/// commit record that follows them is intact. Resetting the log changes the salt, so records
+/// left over from before the reset never pass the checksum.
+#[derive(Debug)]
+pub(crate) struct Wal {
+    storage: Box<dyn Storage + Send>,
+    salt: u64,
+    end: u64,
+    checkpoint_threshold: u64,
+}
+
+impl Wal {
+    pub(crate) fn new(storage: Box<dyn Storage + Send>) -> Self {
+        Self {
+            storage,
+            salt: 0,
+            end: 0,
+            checkpoint_threshold: DEFAULT_CHECKPOINT_THRESHOLD,
+        }
+    }
+
+    pub(crate) fn checkpoint_threshold(&self) -> u64 {
+        self.checkpoint_threshold
+    }
+
+    pub(crate) fn set_checkpoint_threshold(&mut self, checkpoint_threshold: u64) {
+        self.checkpoint_threshold = checkpoint_threshold;
+    }
+
+    pub(crate) fn should_checkpoint(&self) -> bool {
+        self.end > HEADER_SIZE + self.checkpoint_threshold
+    }
+
+    /// Appends pages followed by a commit record and syncs the log.
+    pub(crate) fn append_commit<B: AsRef<[u8]>>(&mut self, pages: &[(usize, B)]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+
+        for (page_index, bytes) in pages {
+            self.encode_record(&mut buffer, PAGE_RECORD, *page_index as u64, bytes.as_ref());
+        }
+        self.encode_record(&mut buffer, COMMIT_RECORD, pages.len() as u64, &[]);
+
+        self.storage.seek(SeekFrom::Start(self.end))?;
+        self.storage.write_all(&buffer)?;
+        self.storage.sync()?;
+        self.end += buffer.len() as u64;
+
+        Ok(())
+    }
+
+    /// Passes the pages of every committed batch to `apply` in the log order. An incomplete or
+    /// damaged tail of the log is ignored.
+    pub(crate) fn replay<E, F>(&mut self, mut apply: F) -> Result<(), E>
+    where
+        E: From<io::Error>,
+        F: FnMut(usize, &[u8]) -> Result<(), E>,
+    {
+        self.storage.seek_to_start()?;
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        match self.storage.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(
+                io::Error::new(ErrorKind::InvalidData, "invalid write-ahead log header").into(),
+            );
+        }
+        self.salt = u64::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+
+        let mut batch = Vec::new();
+        while let Some((kind, value, payload)) = self.read_record()? {
+            match kind {
+                PAGE_RECORD => batch.push((value as usize, payload)),
+                COMMIT_RECORD if value as usize == batch.len() => {
+                    for (page_index, bytes) in batch.drain(..) {
+                        apply(page_index, &bytes)?;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new empty log with a new salt.
+    pub(crate) fn reset(&mut self) -> io::Result<()> {
+        self.salt = self.salt.wrapping_add(1);
+
+        self.storage.seek_to_start()?;
+        self.storage.write_all(MAGIC)?;
+        self.storage.write_all(&self.salt.to_le_bytes())?;
+
+        match self.storage.set_len(HEADER_SIZE) {
+            Err(error) if error.kind() != ErrorKind::Unsupported => return Err(error),
+            _ => {}
+        }
+        self.storage.sync()?;
+        self.end = HEADER_SIZE;
+
+        Ok(())
+    }
+
+    fn encode_record(&self, buffer: &mut Vec<u8>, kind: u8, value: u64, payload: &[u8]) {
+        let start = buffer.len();
+
+        buffer.push(kind);
+        buffer.extend_from_slice(&value.to_le_bytes());
+        buffer.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(payload);
+
+        let mut checksum = Checksum::new(self.salt);
+        checksum.update(&buffer[start..]);
+        buffer.extend_from_slice(&checksum.finish().to_le_bytes());
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<(u8, u64, Vec<u8>)>> {
+        let mut head = [0u8; 17];
+        if !self.read_or_eof(&mut head)? {
+            return Ok(None);
+        }
+
+        let kind = head[0];
+        let value = u64::from_le_bytes(head[1..9].try_into().unwrap());
+        let payload_len = u64::from_le_bytes(head[9..17].try_into().unwrap());
+
+        let mut payload = Vec::new();
+        let read_len = (&mut self.storage)
+            .take(payload_len)
+            .read_to_end(&mut payload)?;
+        let mut stored_checksum = [0u8; 8];
+        if read_len as u64 != payload_len || !self.read_or_eof(&mut stored_checksum)? {
+            return Ok(None);
+        }
+
+        let mut checksum = Checksum::new(self.salt);
+        checksum.update(&head);
+        checksum.update(&payload);
+        if checksum.finish() != u64::from_le_bytes(stored_checksum) {
+            return Ok(None);
+        }
+
+        self.end = self.storage.stream_position()?;
+        Ok(Some((kind, value, payload)))
+    }
+
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> io::Result<bool> {
+        match self.storage.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
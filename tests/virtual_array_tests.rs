@@ -1,4 +1,4 @@
-use virtual_array::VirtualArrayBuilder;
+use virtual_array::{VirtualArrayBuilder, VirtualVec};
 
 fn remove_file(file_name: &str) {
     use std::fs::remove_file;
@@ -160,3 +160,1707 @@ fn test_with_u8_items() {
         assert_eq!(va.get(38).unwrap(), Some(&15));
     }
 }
+
+#[test]
+fn test_in_place_updates() {
+    const FILE_NAME: &str = "test_in_place_updates.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(1)
+            .create(64, 16)
+            .unwrap();
+
+        va.set(0, 10).unwrap();
+        va.set(20, 1).unwrap();
+
+        assert!(va.get_mut(1).unwrap().is_none());
+        *va.get_mut(0).unwrap().unwrap() += 5;
+
+        // evicts the page with index 0, which must be written back
+        assert!(va
+            .update(20, |entry| match entry {
+                virtual_array::Entry::Occupied(mut entry) => {
+                    *entry.get_mut() += 1;
+                    true
+                }
+                virtual_array::Entry::Vacant(_) => false,
+            })
+            .unwrap());
+        assert!(va
+            .update(21, |entry| matches!(entry, virtual_array::Entry::Vacant(_)))
+            .unwrap());
+        va.update(30, |entry| *entry.or_insert(1) += 1).unwrap();
+        assert_eq!(va.get(30).unwrap(), Some(&2));
+        va.update(30, |entry| {
+            if let virtual_array::Entry::Occupied(entry) = entry {
+                entry.remove();
+            }
+        })
+        .unwrap();
+        assert_eq!(va.get(30).unwrap(), None);
+
+        *va.entry(21).unwrap().or_insert(7) *= 2;
+        va.entry(20).unwrap().and_modify(|v| *v += 10).or_insert(0);
+
+        match va.entry(40).unwrap() {
+            virtual_array::Entry::Vacant(entry) => *entry.insert(3) += 1,
+            virtual_array::Entry::Occupied(_) => unreachable!(),
+        }
+    }
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(1)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.get(0).unwrap(), Some(&15));
+        assert_eq!(va.get(20).unwrap(), Some(&12));
+        assert_eq!(va.get(21).unwrap(), Some(&14));
+        assert_eq!(va.get(40).unwrap(), Some(&4));
+
+        match va.entry(40).unwrap() {
+            virtual_array::Entry::Occupied(entry) => assert_eq!(entry.remove(), 4),
+            virtual_array::Entry::Vacant(_) => unreachable!(),
+        }
+        va.flush().unwrap();
+    }
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(1)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.get(40).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_resize() {
+    const FILE_NAME: &str = "test_resize.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u16>()
+            .buffer_size(2)
+            .create(10, 8)
+            .unwrap();
+
+        va.set(3, 30).unwrap();
+        va.set(9, 90).unwrap();
+
+        va.resize(40).unwrap();
+        va.set(39, 390).unwrap();
+    }
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u16>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.len(), 40);
+        assert_eq!(va.get(9).unwrap(), Some(&90));
+        assert_eq!(va.get(39).unwrap(), Some(&390));
+
+        va.resize(5).unwrap();
+        assert!(va.get(9).is_err());
+    }
+
+    let file_len = std::fs::metadata(FILE_NAME).unwrap().len();
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u16>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.len(), 5);
+        assert_eq!(va.get(3).unwrap(), Some(&30));
+
+        va.resize(12).unwrap();
+        assert_eq!(va.get(9).unwrap(), None);
+    }
+
+    assert!(std::fs::metadata(FILE_NAME).unwrap().len() > file_len);
+}
+
+#[test]
+fn test_virtual_vec() {
+    const FILE_NAME: &str = "test_virtual_vec.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u64>()
+            .buffer_size(2)
+            .create(0, 32)
+            .unwrap();
+        let mut vec = VirtualVec::from(va);
+
+        assert!(vec.is_empty());
+        assert_eq!(vec.pop().unwrap(), None);
+
+        for i in 0..10 {
+            vec.push(i).unwrap();
+        }
+        vec.append(100..105).unwrap();
+
+        assert_eq!(vec.len(), 15);
+        assert!(vec.capacity() >= 15);
+        assert_eq!(vec.last().unwrap(), Some(&104));
+        assert_eq!(vec.pop().unwrap(), Some(104));
+        assert!(vec.get(14).is_err());
+    }
+
+    {
+        let va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u64>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+        let mut vec = VirtualVec::from(va);
+
+        assert_eq!(vec.len(), 14);
+        assert_eq!(vec.get(3).unwrap(), Some(&3));
+        assert_eq!(vec.get(13).unwrap(), Some(&103));
+
+        vec.truncate(4).unwrap();
+        vec.push(42).unwrap();
+    }
+
+    {
+        let va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u64>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+        let mut vec = VirtualVec::from(va);
+
+        assert_eq!(vec.len(), 5);
+        assert_eq!(vec.last().unwrap(), Some(&42));
+    }
+}
+
+#[test]
+fn test_fill_and_clear() {
+    use std::ops::Bound;
+
+    const FILE_NAME: &str = "test_fill_and_clear.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .create(50, 40)
+            .unwrap();
+
+        va.set(0, 1).unwrap();
+        va.set(49, 2).unwrap();
+
+        va.fill(3..47, 7).unwrap();
+        va.clear(12..=25).unwrap();
+        assert!(va.fill(40..51, 0).is_err());
+        assert!(va.fill(40..=usize::MAX, 0).is_err());
+        assert!(va
+            .clear((Bound::Excluded(usize::MAX), Bound::Unbounded))
+            .is_err());
+    }
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.get(0).unwrap(), Some(&1));
+        assert_eq!(va.get(2).unwrap(), None);
+        for i in (3..12).chain(26..47) {
+            assert_eq!(va.get(i).unwrap(), Some(&7));
+        }
+        for i in (12..26).chain(47..49) {
+            assert_eq!(va.get(i).unwrap(), None);
+        }
+        assert_eq!(va.get(49).unwrap(), Some(&2));
+
+        va.clear_all().unwrap();
+        for i in 0..50 {
+            assert_eq!(va.get(i).unwrap(), None);
+        }
+    }
+}
+
+#[test]
+fn test_element_movement() {
+    use virtual_array::VirtualArrayError;
+
+    const FILE_NAME: &str = "test_element_movement.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(1)
+        .create(30, 12)
+        .unwrap();
+
+    let mut expected = vec![None; 30];
+    for i in (0..20).filter(|i| i % 4 != 3) {
+        va.set(i, i as u32).unwrap();
+        expected[i] = Some(i as u32);
+    }
+
+    va.swap(1, 3).unwrap();
+    expected.swap(1, 3);
+
+    va.copy_within(2..12, 5).unwrap();
+    expected.copy_within(2..12, 5);
+
+    va.copy_within(9..18, 4).unwrap();
+    expected.copy_within(9..18, 4);
+
+    va.move_range(10..20, 15).unwrap();
+    let moved = expected[10..20].to_vec();
+    expected[10..20].fill(None);
+    expected[15..25].copy_from_slice(&moved);
+
+    va.move_range(15..25, 2).unwrap();
+    let moved = expected[15..25].to_vec();
+    expected[15..25].fill(None);
+    expected[2..12].copy_from_slice(&moved);
+
+    for (i, value) in expected.iter().enumerate() {
+        assert_eq!(va.get(i).unwrap(), value.as_ref(), "index {}", i);
+    }
+
+    assert!(va.copy_within(0..10, 25).is_err());
+
+    assert!(matches!(
+        va.swap(5, 30),
+        Err(VirtualArrayError::IndexOutOfBounds { index: 30, len: 30 })
+    ));
+    assert!(va.swap(30, 5).is_err());
+    drop(va);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(1)
+        .open()
+        .unwrap();
+    assert_eq!(va.get(5).unwrap(), expected[5].as_ref());
+}
+
+#[test]
+fn test_bitmap_scanning() {
+    use virtual_array::page::Bitmap;
+
+    let mut bytes = vec![0u8; 25];
+    bytes[0] = 0b0000_0110;
+    bytes[9] = 0b1000_0000;
+    bytes[24] = 0b0000_0001;
+    let bitmap = Bitmap::new(193, bytes);
+
+    assert_eq!(bitmap.first_set(), Some(1));
+    assert_eq!(bitmap.first_clear(), Some(0));
+    assert_eq!(bitmap.next_set_from(3), Some(79));
+    assert_eq!(bitmap.next_set_from(80), Some(192));
+    assert_eq!(bitmap.prev_set_from(191), Some(79));
+    assert_eq!(bitmap.prev_set_from(0), None);
+    assert_eq!(bitmap.count_ones(), 4);
+    assert_eq!(bitmap.iter_ones().collect::<Vec<_>>(), vec![1, 2, 79, 192]);
+
+    let bitmap = Bitmap::new(10, vec![0xff, 0b0000_0011]);
+    assert_eq!(bitmap.first_clear(), None);
+}
+
+#[test]
+fn test_occupancy_search() {
+    const FILE_NAME: &str = "test_occupancy_search.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u8>()
+        .buffer_size(1)
+        .create(1000, 16)
+        .unwrap();
+
+    assert_eq!(va.next_present(0).unwrap(), None);
+    assert_eq!(va.prev_present(999).unwrap(), None);
+
+    va.fill(0..40, 1).unwrap();
+    va.set(500, 2).unwrap();
+    va.set(999, 3).unwrap();
+
+    assert_eq!(va.first_free().unwrap(), Some(40));
+    assert_eq!(va.next_present(40).unwrap(), Some(500));
+    assert_eq!(va.next_present(501).unwrap(), Some(999));
+    assert_eq!(va.prev_present(998).unwrap(), Some(500));
+    assert_eq!(va.prev_present(499).unwrap(), Some(39));
+    assert_eq!(va.count_present().unwrap(), 42);
+
+    va.fill(.., 0).unwrap();
+    assert_eq!(va.first_free().unwrap(), None);
+}
+
+#[test]
+fn test_compaction() {
+    const FILE_NAME: &str = "test_compaction.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .create(100, 16)
+            .unwrap();
+
+        for i in [2, 3, 17, 40, 41, 99] {
+            va.set(i, i as u32 * 10).unwrap();
+        }
+
+        let mut remapping = Vec::new();
+        let count = va
+            .compact_with(true, |old_index, new_index| {
+                remapping.push((old_index, new_index))
+            })
+            .unwrap();
+        assert_eq!(count, 6);
+        assert_eq!(
+            remapping,
+            vec![(2, 0), (3, 1), (17, 2), (40, 3), (41, 4), (99, 5)]
+        );
+    }
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+
+    assert_eq!(va.len(), 6);
+    for (i, value) in [20, 30, 170, 400, 410, 990].iter().enumerate() {
+        assert_eq!(va.get(i).unwrap(), Some(value));
+    }
+
+    va.delete(1).unwrap();
+    assert_eq!(va.compact(false).unwrap(), 5);
+    assert_eq!(va.len(), 6);
+    assert_eq!(va.get(1).unwrap(), Some(&170));
+    assert_eq!(va.get(5).unwrap(), None);
+}
+
+#[test]
+fn test_external_sort() {
+    const FILE_NAME: &str = "test_external_sort.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<(u32, u32)>()
+        .buffer_size(2)
+        .create(1000, 64)
+        .unwrap();
+
+    let mut expected = Vec::new();
+    let mut state = 12345u32;
+    for i in (0..1000).filter(|i| i % 7 != 0) {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        let value = (state >> 16) % 50;
+        va.set(i, (value, i as u32)).unwrap();
+        expected.push((value, i as u32));
+    }
+
+    va.set_sort_options(virtual_array::SortOptions {
+        memory_budget: 100 * std::mem::size_of::<(u32, u32)>(),
+        ..Default::default()
+    });
+    va.sort_by_key(|item| item.0).unwrap();
+    expected.sort_by_key(|item| item.0);
+
+    for (i, value) in expected.iter().enumerate() {
+        assert_eq!(va.get(i).unwrap(), Some(value));
+    }
+    for i in expected.len()..1000 {
+        assert_eq!(va.get(i).unwrap(), None);
+    }
+
+    va.sort_unstable_by(|a, b| b.1.cmp(&a.1)).unwrap();
+    assert_eq!(
+        va.get(0).unwrap(),
+        Some(&(expected.iter().max_by_key(|item| item.1).unwrap().0, 999))
+    );
+    assert_eq!(va.next_present(0).unwrap(), Some(0));
+    assert_eq!(va.first_free().unwrap(), Some(expected.len()));
+}
+
+#[test]
+fn test_binary_search() {
+    const FILE_NAME: &str = "test_binary_search.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(1)
+        .create(200, 16)
+        .unwrap();
+
+    assert_eq!(va.partition_point(|_| true).unwrap(), 200);
+
+    // values 10, 20, ..., with gaps of absent slots between them
+    for i in (0..150).step_by(3) {
+        va.set(i, (i as u32 / 3 + 1) * 10).unwrap();
+    }
+    va.clear(40..80).unwrap();
+
+    assert_eq!(va.binary_search(&10).unwrap(), Ok(0));
+    assert_eq!(va.binary_search(&130).unwrap(), Ok(36));
+    assert_eq!(va.binary_search(&135).unwrap(), Err(39));
+    assert_eq!(va.binary_search(&150).unwrap(), Err(81));
+    assert_eq!(va.binary_search(&280).unwrap(), Ok(81));
+    assert_eq!(va.binary_search(&5000).unwrap(), Err(200));
+    assert_eq!(va.binary_search_by_key(&50, |x| x * 5).unwrap(), Ok(0));
+
+    assert_eq!(va.lower_bound(&500).unwrap(), 147);
+    assert_eq!(va.upper_bound(&500).unwrap(), 200);
+    assert_eq!(va.lower_bound(&0).unwrap(), 0);
+    assert_eq!(va.partition_point(|x| *x < 140).unwrap(), 39);
+}
+
+#[test]
+fn test_streaming_algorithms() {
+    use virtual_array::algo;
+
+    const FILE_NAME: &str = "test_streaming_algorithms.bin";
+    const TARGET_FILE_NAME: &str = "test_streaming_algorithms_target.bin";
+    remove_file(FILE_NAME);
+    remove_file(TARGET_FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<i64>()
+        .buffer_size(1)
+        .create(100, 32)
+        .unwrap();
+    let mut target = VirtualArrayBuilder::from_file_name(TARGET_FILE_NAME)
+        .item_type::<i64>()
+        .buffer_size(1)
+        .create(100, 32)
+        .unwrap();
+
+    assert_eq!(algo::reduce(&mut va, |a, b| a + b).unwrap(), None);
+
+    for i in (0..100).filter(|i| i % 10 != 0) {
+        va.set(i, i as i64).unwrap();
+    }
+
+    let sum: i64 = (0..100).filter(|i| i % 10 != 0).sum();
+    assert_eq!(algo::fold(&mut va, 0, |a, b| a + b).unwrap(), sum);
+    assert_eq!(algo::reduce(&mut va, |a, b| a.max(*b)).unwrap(), Some(99));
+
+    algo::prefix_sum(&mut va, &mut target).unwrap();
+    assert_eq!(target.get(0).unwrap(), None);
+    assert_eq!(target.get(3).unwrap(), Some(&6));
+    assert_eq!(target.get(10).unwrap(), None);
+    assert_eq!(target.get(11).unwrap(), Some(&(45 + 11)));
+    assert_eq!(target.get(99).unwrap(), Some(&sum));
+
+    let histogram = algo::histogram(&mut va, &[0, 25, 50, 100], |x| *x).unwrap();
+    assert_eq!(histogram, vec![22, 23, 45]);
+
+    let top = algo::top_k(&mut va, 3, |x| x % 50).unwrap();
+    assert_eq!(top, vec![(49, 49), (99, 99), (48, 48)]);
+
+    let sample = algo::sample(&mut va, 5, 7).unwrap();
+    assert_eq!(sample.len(), 5);
+    assert!(sample
+        .iter()
+        .all(|(index, value)| *index as i64 == *value && index % 10 != 0));
+    assert_eq!(sample, algo::sample(&mut va, 5, 7).unwrap());
+}
+
+#[test]
+fn test_parallel_pages() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const FILE_NAME: &str = "test_parallel_pages.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .create(1003, 64)
+        .unwrap();
+
+    va.par_fill(4, 10..1003, 1).unwrap();
+    va.set(0, 100).unwrap();
+
+    let sum = va
+        .par_map_reduce(
+            3,
+            |page| page.iter().map(|(_, value)| *value).sum::<u64>(),
+            |a, b| a + b,
+        )
+        .unwrap();
+    assert_eq!(sum, Some(100 + 993));
+
+    va.par_update(4, |index, value| match value {
+        Some(value) => *value += index as u64,
+        None if index % 2 == 1 => *value = Some(7),
+        None => {}
+    })
+    .unwrap();
+
+    assert_eq!(va.get(0).unwrap(), Some(&100));
+    assert_eq!(va.get(1).unwrap(), Some(&7));
+    assert_eq!(va.get(2).unwrap(), None);
+    assert_eq!(va.get(500).unwrap(), Some(&501));
+
+    let pages = AtomicUsize::new(0);
+    va.par_for_each_page(8, |_| {
+        pages.fetch_add(1, Ordering::Relaxed);
+    })
+    .unwrap();
+    assert_eq!(pages.into_inner(), 1003usize.div_ceil(8));
+}
+
+#[test]
+fn test_write_ahead_log_replay() {
+    const FILE_NAME: &str = "test_write_ahead_log_replay.bin";
+    const CRASHED_FILE_NAME: &str = "test_write_ahead_log_replay_crashed.bin";
+    remove_file(FILE_NAME);
+    remove_file(CRASHED_FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .with_write_ahead_log()
+            .create(1000, 64)
+            .unwrap();
+
+        va.set(1, 10).unwrap();
+        va.set(500, 20).unwrap();
+        va.set(1, 30).unwrap();
+
+        // An array whose in-place writes were lost, with the log left behind.
+        std::fs::copy(
+            format!("{FILE_NAME}.wal"),
+            format!("{CRASHED_FILE_NAME}.wal"),
+        )
+        .unwrap();
+        VirtualArrayBuilder::from_file_name(CRASHED_FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .create(1000, 64)
+            .unwrap();
+
+        va.checkpoint().unwrap();
+        assert_eq!(va.get(1).unwrap(), Some(&30));
+    }
+
+    let mut va = VirtualArrayBuilder::from_file_name(CRASHED_FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .with_write_ahead_log()
+        .open()
+        .unwrap();
+
+    assert_eq!(va.get(1).unwrap(), Some(&30));
+    assert_eq!(va.get(500).unwrap(), Some(&20));
+    assert_eq!(va.get(2).unwrap(), None);
+    drop(va);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    assert_eq!(va.get(500).unwrap(), Some(&20));
+    drop(va);
+
+    // A log storage given to a file array is used instead of a file next to it.
+    let log_file_name = format!("{FILE_NAME}.custom.wal");
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&log_file_name)
+        .unwrap();
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .write_ahead_log(log)
+        .open()
+        .unwrap();
+    let log_size = std::fs::metadata(&log_file_name).unwrap().len();
+    va.set(2, 40).unwrap();
+    assert!(std::fs::metadata(&log_file_name).unwrap().len() > log_size);
+}
+
+#[test]
+fn test_transactions() {
+    const FILE_NAME: &str = "test_transactions.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<i64>()
+        .buffer_size(1)
+        .create(1000, 64)
+        .unwrap();
+
+    va.set(1, 100).unwrap();
+    va.set(900, 50).unwrap();
+
+    // The array file is locked, so it is read through a storage that is not.
+    let read_from_disk = |index| {
+        let mut va = VirtualArrayBuilder::from_storage(std::fs::File::open(FILE_NAME).unwrap())
+            .item_type::<i64>()
+            .buffer_size(1)
+            .read_only()
+            .open()
+            .unwrap();
+        va.get(index).unwrap().copied()
+    };
+
+    let mut tx = va.begin().unwrap();
+    tx.update(1, |entry| {
+        entry.and_modify(|value| *value -= 30);
+    })
+    .unwrap();
+    tx.update(900, |entry| {
+        entry.and_modify(|value| *value += 30);
+    })
+    .unwrap();
+    tx.set(500, 1).unwrap();
+    assert_eq!(tx.get(1).unwrap(), Some(&70));
+    assert_eq!(read_from_disk(1), Some(100));
+    assert_eq!(read_from_disk(900), Some(50));
+    tx.commit().unwrap();
+
+    assert_eq!(read_from_disk(1), Some(70));
+    assert_eq!(read_from_disk(900), Some(80));
+
+    let result = va.transaction(|tx| {
+        tx.set(1, 0)?;
+        tx.delete(900)?;
+        tx.set(1000, 0)
+    });
+    assert!(result.is_err());
+    assert_eq!(va.get(1).unwrap(), Some(&70));
+    assert_eq!(va.get(900).unwrap(), Some(&80));
+
+    let mut tx = va.begin().unwrap();
+    tx.set(2, 2).unwrap();
+    tx.rollback().unwrap();
+    assert_eq!(va.get(2).unwrap(), None);
+
+    let moved = va
+        .transaction(|tx| {
+            let value = tx.get(500)?.copied().unwrap();
+            tx.delete(500)?;
+            tx.set(501, value)?;
+            Ok(value)
+        })
+        .unwrap();
+    assert_eq!(moved, 1);
+    assert_eq!(read_from_disk(501), Some(1));
+}
+
+#[test]
+fn test_shadow_paging() {
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+    use virtual_array::metadata::{self, CommitMode, Serializer};
+
+    const FILE_NAME: &str = "test_shadow_paging.bin";
+    remove_file(FILE_NAME);
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .commit_mode(CommitMode::ShadowPaging)
+            .create(1000, 64)
+            .unwrap();
+
+        va.set(1, 10).unwrap();
+        va.set(999, 5).unwrap();
+        va.resize(2000).unwrap();
+        va.set(1999, 7).unwrap();
+        va.resize(1500).unwrap();
+
+        for i in 0..10 {
+            va.set(2, i).unwrap();
+        }
+        let file_len = std::fs::metadata(FILE_NAME).unwrap().len();
+        for i in 0..200 {
+            va.set(2, i).unwrap();
+        }
+        assert_eq!(std::fs::metadata(FILE_NAME).unwrap().len(), file_len);
+
+        va.set(1, 20).unwrap();
+    }
+
+    {
+        let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap();
+
+        assert_eq!(va.len(), 1500);
+        assert_eq!(va.get(1).unwrap(), Some(&20));
+        assert_eq!(va.get(2).unwrap(), Some(&199));
+        assert_eq!(va.get(999).unwrap(), Some(&5));
+    }
+
+    // Tear the root record of the last commit, which leaves the commit before it. There is one
+    // commit for creating the array, one per set and one per resize.
+    let generation = 1 + 3 + 2 + 210 + 1;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(FILE_NAME)
+        .unwrap();
+    let header = metadata::DefaultSerializer::deserialize::<_, u32>(&mut file, b"VM").unwrap();
+    let roots_start = metadata::DefaultSerializer::get_metadata_size_in_bytes(&header) as u64;
+    file.seek(SeekFrom::Start(roots_start + (generation % 2) * 40 + 39))
+        .unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    assert_eq!(va.get(1).unwrap(), Some(&10));
+    assert_eq!(va.get(2).unwrap(), Some(&199));
+    drop(va);
+
+    let result = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .with_write_ahead_log()
+        .open();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_snapshots() {
+    use virtual_array::metadata::CommitMode;
+
+    for (file_name, commit_mode) in [
+        ("test_snapshots_in_place.bin", CommitMode::InPlace),
+        ("test_snapshots_shadow.bin", CommitMode::ShadowPaging),
+    ] {
+        remove_file(file_name);
+
+        let mut va = VirtualArrayBuilder::from_file_name(file_name)
+            .item_type::<u64>()
+            .buffer_size(2)
+            .commit_mode(commit_mode)
+            .create(1000, 64)
+            .unwrap();
+
+        for i in 0..1000 {
+            va.set(i, i as u64).unwrap();
+        }
+
+        let mut snapshot = va.snapshot().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut sums = Vec::new();
+            for _ in 0..20 {
+                let mut sum = 0;
+                snapshot
+                    .try_for_each_present(|_, value| {
+                        sum += value;
+                        Ok(())
+                    })
+                    .unwrap();
+                sums.push(sum);
+            }
+            (sums, snapshot)
+        });
+
+        for i in 0..1000 {
+            va.set(i, 0).unwrap();
+        }
+        va.par_fill(4, .., 1).unwrap();
+        va.resize(10).unwrap();
+
+        let (sums, mut snapshot) = reader.join().unwrap();
+        assert!(
+            sums.iter().all(|sum| *sum == 999 * 1000 / 2),
+            "{:?} {:?}",
+            commit_mode,
+            sums
+        );
+        assert_eq!(snapshot.len(), 1000);
+        assert_eq!(snapshot.get(999).unwrap(), Some(&999));
+
+        let mut later_snapshot = va.snapshot().unwrap();
+        va.set(0, 5).unwrap();
+        assert_eq!(later_snapshot.len(), 10);
+        assert_eq!(later_snapshot.get(0).unwrap(), Some(&1));
+        assert_eq!(snapshot.get(0).unwrap(), Some(&0));
+        assert_eq!(va.get(0).unwrap(), Some(&5));
+        drop(snapshot);
+        drop(later_snapshot);
+
+        va.set(1, 2).unwrap();
+        let mut snapshot = va.snapshot().unwrap();
+        assert_eq!(snapshot.get(1).unwrap(), Some(&2));
+    }
+}
+
+#[test]
+fn test_close_and_drop_errors() {
+    use std::{
+        io::{Cursor, Read, Seek, SeekFrom, Write},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use virtual_array::{Storage, VirtualArrayError};
+
+    #[derive(Debug)]
+    struct FailingStorage {
+        inner: Cursor<Vec<u8>>,
+        is_failing: Arc<AtomicBool>,
+    }
+
+    impl Read for FailingStorage {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for FailingStorage {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.is_failing.load(Ordering::Relaxed) {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FailingStorage {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl Storage for FailingStorage {}
+
+    static DROP_ERRORS: AtomicUsize = AtomicUsize::new(0);
+    fn count_drop_error(_: &VirtualArrayError) {
+        DROP_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    const FILE_NAME: &str = "test_close_and_drop_errors.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 64)
+        .unwrap();
+    va.entry(3).unwrap().or_insert(3);
+    let file = va.close().unwrap();
+    assert!(file.metadata().unwrap().len() > 0);
+    drop(file);
+
+    let mut vec = VirtualVec::from(
+        VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap(),
+    );
+    assert_eq!(vec.capacity(), 100);
+    vec.push(7).unwrap();
+    vec.close().unwrap();
+
+    let is_failing = Arc::new(AtomicBool::new(false));
+    let storage = FailingStorage {
+        inner: Cursor::new(Vec::new()),
+        is_failing: Arc::clone(&is_failing),
+    };
+    let mut va = VirtualArrayBuilder::from_storage(storage)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 64)
+        .unwrap();
+    va.set_drop_error_hook(count_drop_error);
+
+    is_failing.store(true, Ordering::Relaxed);
+    va.entry(1).unwrap().or_insert(1);
+    drop(va);
+    assert_eq!(DROP_ERRORS.load(Ordering::Relaxed), 1);
+
+    is_failing.store(false, Ordering::Relaxed);
+    let storage = FailingStorage {
+        inner: Cursor::new(Vec::new()),
+        is_failing: Arc::clone(&is_failing),
+    };
+    let mut va = VirtualArrayBuilder::from_storage(storage)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 64)
+        .unwrap();
+    va.set_drop_error_hook(count_drop_error);
+
+    is_failing.store(true, Ordering::Relaxed);
+    va.entry(1).unwrap().or_insert(1);
+    assert!(va.close().is_err());
+    assert_eq!(DROP_ERRORS.load(Ordering::Relaxed), 1);
+
+    // A storage that cannot be truncated keeps the bytes past the last page on shrinking.
+    is_failing.store(false, Ordering::Relaxed);
+    let storage = FailingStorage {
+        inner: Cursor::new(Vec::new()),
+        is_failing,
+    };
+    let mut va = VirtualArrayBuilder::from_storage(storage)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 64)
+        .unwrap();
+    va.set(5, 5).unwrap();
+    va.set(50, 50).unwrap();
+    va.resize(60).unwrap();
+    assert_eq!(va.compact(true).unwrap(), 2);
+    assert_eq!(va.len(), 2);
+    assert_eq!(va.get(1).unwrap(), Some(&50));
+    va.close().unwrap();
+}
+
+#[test]
+fn test_backup_and_restore() {
+    use std::fs::File;
+
+    const FILE_NAME: &str = "test_backup_and_restore.bin";
+    const BACKUP_FILE_NAME: &str = "test_backup_and_restore_backup.bin";
+    remove_file(FILE_NAME);
+    remove_file(BACKUP_FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .create(1000, 64)
+        .unwrap();
+    for i in (0..1000).step_by(3) {
+        va.set(i, i as u64).unwrap();
+    }
+
+    let snapshot = va.snapshot().unwrap();
+    let backup = std::thread::spawn(move || {
+        let mut calls = Vec::new();
+        let file = File::create(BACKUP_FILE_NAME).unwrap();
+        snapshot
+            .backup_to(file, |done, total| calls.push((done, total)))
+            .unwrap();
+        calls
+    });
+    for i in 0..1000 {
+        va.set(i, 0).unwrap();
+    }
+    let calls = backup.join().unwrap();
+    assert_eq!(calls.len(), 1000 / 8 + 1);
+    assert_eq!(calls.last(), Some(&(126, 126)));
+
+    let mut copy = VirtualArrayBuilder::from_file_name(BACKUP_FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    assert_eq!(copy.get(999).unwrap(), Some(&999));
+    assert_eq!(copy.get(998).unwrap(), None);
+    drop(copy);
+
+    va.resize(10).unwrap();
+    va.restore_from(File::open(BACKUP_FILE_NAME).unwrap())
+        .unwrap();
+    assert_eq!(va.len(), 1000);
+    assert_eq!(va.get(3).unwrap(), Some(&3));
+    assert_eq!(va.get(4).unwrap(), None);
+    assert_eq!(va.get(999).unwrap(), Some(&999));
+
+    let image = va
+        .backup_to(File::create(BACKUP_FILE_NAME).unwrap(), |_, _| {})
+        .unwrap();
+    image.set_len(image.metadata().unwrap().len() - 1).unwrap();
+    va.set(3, 4).unwrap();
+
+    assert!(va
+        .restore_from(File::open(BACKUP_FILE_NAME).unwrap())
+        .is_err());
+    assert_eq!(va.get(3).unwrap(), Some(&4));
+}
+
+#[test]
+fn test_incremental_backups() {
+    use std::fs::File;
+
+    const FILE_NAME: &str = "test_incremental_backups.bin";
+    const RESTORED_FILE_NAME: &str = "test_incremental_backups_restored.bin";
+    const FULL_FILE_NAME: &str = "test_incremental_backups_full.bin";
+    const FIRST_FILE_NAME: &str = "test_incremental_backups_first.bin";
+    const SECOND_FILE_NAME: &str = "test_incremental_backups_second.bin";
+    for file_name in [
+        FILE_NAME,
+        RESTORED_FILE_NAME,
+        FULL_FILE_NAME,
+        FIRST_FILE_NAME,
+        SECOND_FILE_NAME,
+    ] {
+        remove_file(file_name);
+    }
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .create(1000, 64)
+        .unwrap();
+    for i in (0..1000).step_by(3) {
+        va.set(i, i as u64).unwrap();
+    }
+
+    let snapshot = va.snapshot().unwrap();
+    let full_generation = snapshot.generation();
+    snapshot
+        .backup_to(File::create(FULL_FILE_NAME).unwrap(), |_, _| {})
+        .unwrap();
+    drop(snapshot);
+
+    va.set(10, 10).unwrap();
+    va.delete(501).unwrap();
+    let snapshot = va.snapshot().unwrap();
+    let first_generation = snapshot.generation();
+    let mut calls = Vec::new();
+    snapshot
+        .incremental_backup_since(
+            full_generation,
+            File::create(FIRST_FILE_NAME).unwrap(),
+            |done, total| calls.push((done, total)),
+        )
+        .unwrap();
+    drop(snapshot);
+    assert_eq!(calls, [(1, 2), (2, 2)]);
+
+    va.resize(1200).unwrap();
+    va.set(1100, 1100).unwrap();
+    va.set(11, 11).unwrap();
+    va.incremental_backup_since(
+        first_generation,
+        File::create(SECOND_FILE_NAME).unwrap(),
+        |_, _| {},
+    )
+    .unwrap();
+
+    let mut restored = VirtualArrayBuilder::from_file_name(RESTORED_FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .create(10, 64)
+        .unwrap();
+    restored
+        .restore_from(File::open(FULL_FILE_NAME).unwrap())
+        .unwrap();
+    assert!(restored
+        .apply_incremental(File::open(SECOND_FILE_NAME).unwrap())
+        .is_err());
+    restored
+        .apply_incremental(File::open(FIRST_FILE_NAME).unwrap())
+        .unwrap();
+    restored
+        .apply_incremental(File::open(SECOND_FILE_NAME).unwrap())
+        .unwrap();
+
+    assert_eq!(restored.len(), 1200);
+    for i in 0..1200 {
+        assert_eq!(restored.get(i).unwrap(), va.get(i).unwrap(), "index {i}");
+    }
+
+    let corrupted = File::options().write(true).open(SECOND_FILE_NAME).unwrap();
+    corrupted
+        .set_len(corrupted.metadata().unwrap().len() - 1)
+        .unwrap();
+    let mut restored_again = VirtualArrayBuilder::from_file_name(FULL_FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    restored_again
+        .apply_incremental(File::open(FIRST_FILE_NAME).unwrap())
+        .unwrap();
+    assert!(restored_again
+        .apply_incremental(File::open(SECOND_FILE_NAME).unwrap())
+        .is_err());
+    assert_eq!(restored_again.len(), 1000);
+}
+
+#[test]
+fn test_versioned_reads() {
+    use virtual_array::VirtualArrayError;
+
+    const FILE_NAME: &str = "test_versioned_reads.bin";
+    remove_file(FILE_NAME);
+    remove_file("test_versioned_reads.bin.versions");
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .with_version_log(3)
+        .create(100, 16)
+        .unwrap();
+
+    let empty = va.generation();
+    for i in 0..10 {
+        va.set(i, i as u32).unwrap();
+    }
+    va.flush().unwrap();
+
+    let first = va.generation();
+    va.set(42, 1).unwrap();
+    va.flush().unwrap();
+
+    va.set(42, 2).unwrap();
+    va.delete(1).unwrap();
+    va.resize(200).unwrap();
+    va.set(150, 150).unwrap();
+    va.par_fill(4, 60..70, 7).unwrap();
+    va.flush().unwrap();
+
+    assert_eq!(va.get_at(42, empty).unwrap(), None);
+    assert_eq!(va.get_at(42, first).unwrap(), Some(1));
+    assert_eq!(va.get_at(42, va.generation()).unwrap(), Some(2));
+    assert_eq!(va.get_at(1, first).unwrap(), Some(1));
+    assert_eq!(va.get_at(65, first).unwrap(), None);
+    assert!(matches!(
+        va.get_at(150, first),
+        Err(VirtualArrayError::IndexOutOfBounds { len: 100, .. })
+    ));
+
+    let values = va
+        .iter_at(first)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let expected = (0..10)
+        .map(|i| (i, i as u32))
+        .chain([(42, 1)])
+        .collect::<Vec<_>>();
+    assert_eq!(values, expected);
+    drop(va);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .with_version_log(3)
+        .open()
+        .unwrap();
+    assert_eq!(va.get_at(42, first).unwrap(), Some(1));
+
+    let before_rollback = va.generation() - 1;
+    va.rollback_to(first).unwrap();
+    assert_eq!(va.len(), 100);
+    assert_eq!(va.get(42).unwrap(), Some(&1));
+    assert_eq!(va.get(1).unwrap(), Some(&1));
+    assert_eq!(va.get(65).unwrap(), None);
+    va.flush().unwrap();
+    assert_eq!(va.get_at(42, before_rollback).unwrap(), Some(2));
+
+    for i in 0..3 {
+        va.set(i, 0).unwrap();
+        va.flush().unwrap();
+    }
+    assert!(matches!(
+        va.get_at(42, first),
+        Err(VirtualArrayError::GenerationUnavailable { .. })
+    ));
+    assert!(va.get_at(0, va.generation() + 1).is_err());
+}
+
+#[test]
+fn test_file_locking() {
+    use std::time::Duration;
+    use virtual_array::{LockBehavior, VirtualArrayError};
+
+    const FILE_NAME: &str = "test_file_locking.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.set(5, 5).unwrap();
+
+    let open = |is_read_only: bool, lock_behavior| {
+        let builder = VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .lock_behavior(lock_behavior);
+        if is_read_only {
+            builder.read_only().open()
+        } else {
+            builder.open()
+        }
+    };
+
+    assert!(matches!(
+        open(false, LockBehavior::FailFast),
+        Err(VirtualArrayError::Locked)
+    ));
+    assert!(matches!(
+        open(true, LockBehavior::Wait(Duration::from_millis(50))),
+        Err(VirtualArrayError::Locked)
+    ));
+
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        drop(va);
+    });
+    let mut va = open(false, LockBehavior::Wait(Duration::from_secs(10))).unwrap();
+    writer.join().unwrap();
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    drop(va);
+
+    let mut reader = open(true, LockBehavior::FailFast).unwrap();
+    let mut other_reader = open(true, LockBehavior::FailFast).unwrap();
+    assert_eq!(reader.get(5).unwrap(), Some(&5));
+    assert_eq!(other_reader.get(5).unwrap(), Some(&5));
+    assert!(matches!(reader.set(5, 6), Err(VirtualArrayError::ReadOnly)));
+    assert!(matches!(
+        reader.resize(10),
+        Err(VirtualArrayError::ReadOnly)
+    ));
+    assert!(matches!(
+        open(false, LockBehavior::FailFast),
+        Err(VirtualArrayError::Locked)
+    ));
+    reader.flush().unwrap();
+}
+
+#[test]
+fn test_read_only_rejects_writes_unchanged() {
+    use virtual_array::VirtualArrayError;
+
+    const FILE_NAME: &str = "test_read_only_rejects_writes_unchanged.bin";
+    remove_file(FILE_NAME);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.set(0, 0).unwrap();
+    va.set(1, 1).unwrap();
+    va.set(50, 50).unwrap();
+    va.close().unwrap();
+
+    let mut reader = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .read_only()
+        .open()
+        .unwrap();
+    let is_read_only = |result| matches!(result, Err(VirtualArrayError::ReadOnly));
+
+    // A rejected write leaves the buffered pages as they were.
+    assert!(is_read_only(reader.fill(0..2, 42)));
+    assert_eq!(reader.get(1).unwrap(), Some(&1));
+    assert!(is_read_only(reader.swap(1, 50)));
+    assert_eq!(reader.get(1).unwrap(), Some(&1));
+    assert_eq!(reader.get(50).unwrap(), Some(&50));
+    assert!(is_read_only(reader.clear(0..2)));
+    assert!(is_read_only(reader.copy_within(0..2, 10)));
+    assert!(is_read_only(reader.move_range(0..2, 10)));
+    assert!(is_read_only(reader.compact(false).map(|_| ())));
+    assert!(is_read_only(reader.sort_by(|a, b| b.cmp(a))));
+    assert!(is_read_only(reader.clear_all()));
+
+    assert_eq!(reader.get(0).unwrap(), Some(&0));
+    assert_eq!(reader.get(1).unwrap(), Some(&1));
+    assert_eq!(reader.get(10).unwrap(), None);
+    assert_eq!(reader.get(50).unwrap(), Some(&50));
+}
+
+#[test]
+fn test_refresh_after_external_changes() {
+    use std::time::Duration;
+    use virtual_array::LockBehavior;
+
+    const FILE_NAME: &str = "test_refresh_after_external_changes.bin";
+    remove_file(FILE_NAME);
+
+    let mut writer = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    writer.set(5, 5).unwrap();
+    writer.set(50, 50).unwrap();
+    writer.flush().unwrap();
+
+    let mut reader = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(4)
+        .read_only()
+        .lock_behavior(LockBehavior::Unlocked)
+        .open()
+        .unwrap();
+    assert_eq!(reader.get(5).unwrap(), Some(&5));
+    assert_eq!(reader.get(50).unwrap(), Some(&50));
+    assert!(!reader.refresh().unwrap());
+
+    let generation = writer.generation();
+    writer.set(5, 6).unwrap();
+    writer.resize(200).unwrap();
+    writer.set(150, 150).unwrap();
+    assert_eq!(reader.get(5).unwrap(), Some(&5));
+    writer.flush().unwrap();
+    assert_eq!(writer.generation(), generation + 1);
+    writer.flush().unwrap();
+    assert_eq!(writer.generation(), generation + 1);
+
+    assert!(reader.refresh().unwrap());
+    assert_eq!(reader.len(), 200);
+    assert_eq!(reader.get(5).unwrap(), Some(&6));
+    assert_eq!(reader.get(50).unwrap(), Some(&50));
+    assert_eq!(reader.get(150).unwrap(), Some(&150));
+
+    reader.set_refresh_interval(Some(Duration::ZERO));
+    writer.delete(50).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(reader.get(50).unwrap(), None);
+}
+
+#[test]
+fn test_shutdown_status_and_verification() {
+    use std::io::ErrorKind;
+    use virtual_array::{ShutdownStatus, VirtualArrayError};
+
+    const FILE_NAME: &str = "test_shutdown_status_and_verification.bin";
+    remove_file(FILE_NAME);
+
+    let open = || {
+        VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap()
+    };
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.set(5, 5).unwrap();
+    va.close().unwrap();
+
+    let mut va = open();
+    assert_eq!(va.shutdown_status(), ShutdownStatus::Clean);
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    drop(va);
+
+    let mut va = open();
+    assert_eq!(va.shutdown_status(), ShutdownStatus::Clean);
+    va.set(99, 99).unwrap();
+    va.flush().unwrap();
+    drop(va);
+
+    let mut va = open();
+    assert_eq!(va.shutdown_status(), ShutdownStatus::Unclean);
+    va.verify().unwrap();
+    assert_eq!(va.get(99).unwrap(), Some(&99));
+    va.close().unwrap();
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(FILE_NAME)
+        .unwrap();
+    file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+    drop(file);
+
+    let mut va = open();
+    assert_eq!(va.shutdown_status(), ShutdownStatus::Clean);
+    assert!(matches!(
+        va.verify(),
+        Err(VirtualArrayError::IoError(error)) if error.kind() == ErrorKind::InvalidData
+    ));
+}
+
+#[test]
+fn test_header_feature_flags() {
+    use std::{fs::OpenOptions, io::Seek};
+    use virtual_array::metadata::{self, Metadata, SerializationError, Serializer};
+    use virtual_array::VirtualArrayError;
+
+    const FILE_NAME: &str = "test_header_feature_flags.bin";
+    remove_file(FILE_NAME);
+
+    let open_file = || {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(FILE_NAME)
+            .unwrap()
+    };
+    let read_header =
+        || metadata::DefaultSerializer::deserialize::<_, u32>(&mut open_file(), b"VM").unwrap();
+    let write_header = |header: &Metadata| {
+        let mut file = open_file();
+        file.rewind().unwrap();
+        metadata::DefaultSerializer::serialize(&mut file, header).unwrap();
+    };
+    let open = || {
+        VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+    };
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.set(5, 5).unwrap();
+    va.close().unwrap();
+
+    let mut header = read_header();
+    header.compat_features = 1 << 40;
+    write_header(&header);
+
+    let mut va = open().unwrap();
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    va.set(6, 6).unwrap();
+    va.close().unwrap();
+
+    let mut header = read_header();
+    assert_eq!(header.compat_features, 1 << 40);
+    header.incompat_features = 1 << 3;
+    write_header(&header);
+    assert!(matches!(
+        open(),
+        Err(VirtualArrayError::MetadataSerializationError(
+            SerializationError::UnknownIncompatFeatures(features)
+        )) if features == 1 << 3
+    ));
+
+    header.incompat_features = 0;
+    write_header(&header);
+    let mut va = open().unwrap();
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    assert_eq!(va.get(6).unwrap(), Some(&6));
+}
+
+#[test]
+fn test_portable_header() {
+    const FILE_NAME: &str = "test_portable_header.bin";
+    remove_file(FILE_NAME);
+
+    let open = || {
+        VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap()
+    };
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.set(5, 5).unwrap();
+    va.close().unwrap();
+
+    // The page size is the first field after the 2 byte signature and the 28 byte preamble.
+    let bytes = std::fs::read(FILE_NAME).unwrap();
+    assert_eq!(bytes[30..38], 16u64.to_le_bytes());
+    assert_eq!(bytes[38..46], 100u64.to_le_bytes());
+
+    let mut va = open();
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+}
+
+#[test]
+fn test_unversioned_header() {
+    const FILE_NAME: &str = "test_unversioned_header.bin";
+    remove_file(FILE_NAME);
+
+    // A header without the versioned preamble, followed by pages of 4 items and a bitmap byte
+    // that have no generation. Only element 5, the second item of the second page, is set.
+    let mut bytes = b"VM".to_vec();
+    bytes.extend_from_slice(&16usize.to_ne_bytes());
+    bytes.extend_from_slice(&100usize.to_ne_bytes());
+    for page_index in 0..26u32 {
+        for index_on_page in 0..4 {
+            bytes.extend_from_slice(&(page_index * 4 + index_on_page).to_ne_bytes());
+        }
+        bytes.push(if page_index == 1 { 0b10 } else { 0 });
+    }
+    // Bytes after the last page, which the header does not account for, are kept.
+    bytes.extend_from_slice(&[0xAB; 17]);
+    std::fs::write(FILE_NAME, &bytes).unwrap();
+
+    let open = || {
+        VirtualArrayBuilder::from_file_name(FILE_NAME)
+            .item_type::<u32>()
+            .buffer_size(2)
+            .open()
+            .unwrap()
+    };
+
+    let mut va = open();
+    assert_eq!(va.len(), 100);
+    assert_eq!(va.get(4).unwrap(), None);
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    va.set(6, 60).unwrap();
+    va.close().unwrap();
+
+    // The array is written back in the layout it was read in.
+    let header_size = 2 + 2 * std::mem::size_of::<usize>();
+    let written = std::fs::read(FILE_NAME).unwrap();
+    assert_eq!(written.len(), bytes.len());
+    assert_eq!(written[..header_size], bytes[..header_size]);
+
+    let mut va = open();
+    assert_eq!(va.get(5).unwrap(), Some(&5));
+    assert_eq!(va.get(6).unwrap(), Some(&60));
+    assert_eq!(va.get(7).unwrap(), None);
+}
+
+#[test]
+fn test_item_type_mismatch() {
+    use std::{fs::File, num::NonZeroU64};
+    use virtual_array::VirtualArrayError;
+
+    const FILE_NAME: &str = "test_item_type_mismatch.bin";
+    remove_file(FILE_NAME);
+
+    let va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<f32>()
+        .item_type_name("f32")
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.close().unwrap();
+
+    let result = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u64>()
+        .buffer_size(2)
+        .open();
+    match result {
+        Err(VirtualArrayError::ItemTypeMismatch { expected, found }) => {
+            assert_eq!((expected.size, expected.align), (8, 8));
+            assert_eq!((found.size, found.align), (4, 4));
+        }
+        _ => panic!("opened an f32 array as u64"),
+    }
+
+    let result = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .item_type_name("u32")
+        .buffer_size(2)
+        .open();
+    assert!(matches!(
+        result,
+        Err(VirtualArrayError::ItemTypeMismatch { .. })
+    ));
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<f32>()
+        .item_type_name("f32")
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    va.set(1, 1.5).unwrap();
+    drop(va);
+
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<f32>()
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    assert_eq!(va.get(1).unwrap(), Some(&1.5));
+
+    // Explicit schemas are non-zero, so any of them is recorded.
+    remove_file(FILE_NAME);
+    let schema = |value| NonZeroU64::new(value).unwrap();
+    let va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .item_schema(schema(1))
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    va.close().unwrap();
+
+    let result = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .item_schema(schema(2))
+        .buffer_size(2)
+        .open();
+    match result {
+        Err(VirtualArrayError::ItemTypeMismatch { expected, found }) => {
+            assert_eq!(expected.schema, Some(schema(2)));
+            assert_eq!(found.schema, Some(schema(1)));
+        }
+        _ => panic!("opened an array with a different schema"),
+    }
+
+    // Backups are checked the same way before they are restored.
+    const BACKUP_FILE_NAME: &str = "test_item_type_mismatch_backup.bin";
+    const INCREMENT_FILE_NAME: &str = "test_item_type_mismatch_increment.bin";
+    remove_file(BACKUP_FILE_NAME);
+    remove_file(INCREMENT_FILE_NAME);
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .item_schema(schema(1))
+        .buffer_size(2)
+        .open()
+        .unwrap();
+    va.set(5, 5).unwrap();
+    va.backup_to(File::create(BACKUP_FILE_NAME).unwrap(), |_, _| {})
+        .unwrap();
+    va.incremental_backup_since(0, File::create(INCREMENT_FILE_NAME).unwrap(), |_, _| {})
+        .unwrap();
+    va.close().unwrap();
+
+    remove_file(FILE_NAME);
+    let mut va = VirtualArrayBuilder::from_file_name(FILE_NAME)
+        .item_type::<u32>()
+        .item_schema(schema(2))
+        .buffer_size(2)
+        .create(100, 16)
+        .unwrap();
+    assert!(matches!(
+        va.restore_from(File::open(BACKUP_FILE_NAME).unwrap()),
+        Err(VirtualArrayError::ItemTypeMismatch { .. })
+    ));
+    assert!(matches!(
+        va.apply_incremental(File::open(INCREMENT_FILE_NAME).unwrap()),
+        Err(VirtualArrayError::ItemTypeMismatch { .. })
+    ));
+    assert_eq!(va.get(5).unwrap(), None);
+}